@@ -1,9 +1,13 @@
-use std::{net::SocketAddr, path, time::Duration};
+use std::{net::SocketAddr, path, str::FromStr, time::Duration};
 use commons::error::Error;
 
 pub fn fetch_conf() -> Result<Conf, Error> {
     let mut conf = Conf::default();
-    let args = std::env::args().skip(1).collect::<Vec<String>>();
+    // `--property=value` is read the same as `--property value`
+    let args = std::env::args().skip(1).flat_map(|arg| match arg.starts_with("--") {
+        true => arg.splitn(2, '=').map(str::to_string).collect::<Vec<String>>(),
+        false => vec![arg],
+    }).collect::<Vec<String>>();
     let mut it = args.into_iter().peekable();
 
     while let Some(prop) = it.next() {
@@ -60,6 +64,81 @@ pub fn fetch_conf() -> Result<Conf, Error> {
                     return Err(e);
                 },
             },
+            "--limit" | "-l" => match it.next() {
+                None => {
+                    let err = "No value provided for limit";
+                    eprintln!("{err}");
+                    return Err(Error::invalid_argument(err));
+                },
+                Some(l) => conf.write_limit = Some(l.parse().inspect_err(|e| eprintln!("{e}"))?),
+            },
+            "--read-limit" => match it.next() {
+                None => {
+                    let err = "No value provided for read limit";
+                    eprintln!("{err}");
+                    return Err(Error::invalid_argument(err));
+                },
+                Some(l) => conf.read_limit = Some(l.parse().inspect_err(|e| eprintln!("{e}"))?),
+            },
+            "--preserve" => match it.next() {
+                None => {
+                    let err = "No value provided for preserve";
+                    eprintln!("{err}");
+                    return Err(Error::invalid_argument(err));
+                },
+                Some(p) => conf.preserve = p.parse().inspect_err(|e| eprintln!("{e}"))?,
+            },
+            "--symlinks" => match it.next() {
+                None => {
+                    let err = "No value provided for symlinks";
+                    eprintln!("{err}");
+                    return Err(Error::invalid_argument(err));
+                },
+                Some(p) => conf.symlinks = p.parse().inspect_err(|e| eprintln!("{e}"))?,
+            },
+            "--quick-check" => match it.next() {
+                None => {
+                    let err = "No value provided for quick check";
+                    eprintln!("{err}");
+                    return Err(Error::invalid_argument(err));
+                },
+                Some(q) => conf.quick_check = Some(q.parse().inspect_err(|e| eprintln!("{e}"))?),
+            },
+            "--delta" => conf.delta = true,
+            "--manifest" => match it.next() {
+                None => {
+                    let err = "No value provided for manifest";
+                    eprintln!("{err}");
+                    return Err(Error::invalid_argument(err));
+                },
+                Some(m) => conf.manifest = Some(m.parse().inspect_err(|e| eprintln!("{e}"))?),
+            },
+            "--fsync" => match it.next() {
+                None => {
+                    let err = "No value provided for fsync";
+                    eprintln!("{err}");
+                    return Err(Error::invalid_argument(err));
+                },
+                Some(f) => conf.fsync = f.parse().inspect_err(|e| eprintln!("{e}"))?,
+            },
+            "--conflict" => match it.next() {
+                None => {
+                    let err = "No value provided for conflict";
+                    eprintln!("{err}");
+                    return Err(Error::invalid_argument(err));
+                },
+                Some(c) => conf.conflict = c.parse().inspect_err(|e| eprintln!("{e}"))?,
+            },
+            "--mirror" => conf.mirror = true,
+            "--dry-run" => conf.dry_run = true,
+            "--max-deletions" => match it.next() {
+                None => {
+                    let err = "No value provided for max deletions";
+                    eprintln!("{err}");
+                    return Err(Error::invalid_argument(err));
+                },
+                Some(m) => conf.max_deletions = Some(m.parse().inspect_err(|e| eprintln!("{e}"))?),
+            },
             // add more configurations here
             _ => {
                 let err = format!("only one source and destination are allowed. Reading {prop}");
@@ -77,10 +156,34 @@ pub struct Conf {
     source: Option<path::PathBuf>,
     sink: Option<path::PathBuf>,
     role: Option<commons::Role>,
-    socket: Option<SocketAddr>,
+    socket: Option<Address>,
     pub write_timeout: Option<std::time::Duration>,
     pub compression: Option<Box<dyn commons::compression::Compression>>,
     pub checksum: Option<Box<dyn commons::checksum::Checksum>>,
+    /// file attributes carried over to the receiver
+    pub preserve: commons::Preserve,
+    /// how links inside the source tree are sent
+    pub symlinks: commons::SymlinkPolicy,
+    /// skip files the receiver already has
+    pub quick_check: Option<commons::quick_check::QuickCheck>,
+    /// send only the changed blocks of files the receiver has
+    pub delta: bool,
+    /// delete what the source doesn't have from the destination
+    pub mirror: bool,
+    /// refuse to mirror when more entries than this would be deleted
+    pub max_deletions: Option<usize>,
+    /// negotiate and report without transferring anything
+    pub dry_run: bool,
+    /// what the receiver does with files already at the destination
+    pub conflict: commons::Conflict,
+    /// when the receiver flushes what it wrote to disk
+    pub fsync: commons::Fsync,
+    /// list every entry before any data, with sizes or digests
+    pub manifest: Option<commons::manifest::Detail>,
+    /// bandwidth cap on bytes sent
+    pub write_limit: Option<commons::throttle::Throttle>,
+    /// bandwidth cap on bytes received
+    pub read_limit: Option<commons::throttle::Throttle>,
 }
 
 impl Default for Conf {
@@ -94,6 +197,18 @@ impl Default for Conf {
             write_timeout: settings.write_timeout.map(Duration::from_secs),
             compression: None,
             checksum: None,
+            preserve: commons::Preserve::default(),
+            symlinks: commons::SymlinkPolicy::default(),
+            quick_check: None,
+            delta: settings.delta.unwrap_or_default(),
+            mirror: settings.mirror.unwrap_or_default(),
+            max_deletions: settings.max_deletions.map(|m| m as usize),
+            dry_run: false,
+            conflict: commons::Conflict::default(),
+            fsync: commons::Fsync::default(),
+            manifest: None,
+            write_limit: None,
+            read_limit: None,
         };
 
         if let Some(source) = settings.source {
@@ -123,6 +238,38 @@ impl Default for Conf {
         if let Some(c) = settings.checksum {
             let _ = config.checksum(&c);
         }
+
+        if let Some(p) = settings.preserve {
+            config.preserve = p.parse().inspect_err(|e| eprintln!("{e}")).unwrap_or_default();
+        }
+
+        if let Some(s) = settings.symlinks {
+            config.symlinks = s.parse().inspect_err(|e| eprintln!("{e}")).unwrap_or_default();
+        }
+
+        if let Some(q) = settings.quick_check {
+            config.quick_check = q.parse().inspect_err(|e| eprintln!("{e}")).ok();
+        }
+
+        if let Some(c) = settings.conflict {
+            config.conflict = c.parse().inspect_err(|e| eprintln!("{e}")).unwrap_or_default();
+        }
+
+        if let Some(m) = settings.manifest {
+            config.manifest = m.parse().inspect_err(|e| eprintln!("{e}")).ok();
+        }
+
+        if let Some(f) = settings.fsync {
+            config.fsync = f.parse().inspect_err(|e| eprintln!("{e}")).unwrap_or_default();
+        }
+
+        if let Some(l) = settings.limit {
+            config.write_limit = l.parse().inspect_err(|e| eprintln!("{e}")).ok();
+        }
+
+        if let Some(l) = settings.read_limit {
+            config.read_limit = l.parse().inspect_err(|e| eprintln!("{e}")).ok();
+        }
         config
     }
 }
@@ -136,8 +283,12 @@ impl Conf {
         self.sink.take().ok_or(Error::invalid_argument("no sink path defined"))
     }
 
-    pub fn socket(&self) -> Result<SocketAddr, Error> {
-        self.socket.ok_or(Error::invalid_argument("no socket path defined"))
+    pub fn socket(&mut self) -> Result<Address, Error> {
+        self.socket.take().ok_or(Error::invalid_argument("no socket path defined"))
+    }
+
+    pub fn mirror(&self) -> Option<commons::mirror::Mirror> {
+        self.mirror.then(|| commons::mirror::Mirror::default().with_max_deletions(self.max_deletions))
     }
 
     pub fn role(&self) -> Result<commons::Role, Error> {
@@ -176,7 +327,7 @@ impl Conf {
     /// only one socket address is allowed, either with source or with sink
     fn set_socket(&mut self, socket: &str, role: commons::Role, cli: bool) -> Result<(), Error> {
         if !cli || self.socket.is_none() {
-            let s = socket.parse::<SocketAddr>()?;
+            let s = socket.parse::<Address>()?;
             self.socket = Some(s);
             self.role = Some(role);
             Ok(())
@@ -207,6 +358,35 @@ impl Conf {
     }
 }
 
+/// Address of the remote server
+#[derive(Debug, PartialEq)]
+pub enum Address {
+    Tcp(SocketAddr),
+    /// unix domain socket path, written as `unix:/path/to/socket`
+    Unix(path::PathBuf),
+}
+
+impl FromStr for Address {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.strip_prefix("unix:") {
+            Some("") => Err(Error::invalid_argument("no unix socket path provided")),
+            Some(p) => Ok(Self::Unix(path::PathBuf::from(p))),
+            None => Ok(Self::Tcp(s.parse::<SocketAddr>()?)),
+        }
+    }
+}
+
+impl std::fmt::Display for Address {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Tcp(s) => write!(f, "{s}"),
+            Self::Unix(p) => write!(f, "unix:{}", p.display()),
+        }
+    }
+}
+
 mod file_config {
     const FILE_NAME: &str = "atilink-conf.toml";
 
@@ -218,6 +398,17 @@ mod file_config {
         pub checksum: Option<String>,
         pub chunk_bytes: Option<u64>,
         pub write_timeout: Option<u64>,
+        pub preserve: Option<String>,
+        pub symlinks: Option<String>,
+        pub quick_check: Option<String>,
+        pub delta: Option<bool>,
+        pub mirror: Option<bool>,
+        pub max_deletions: Option<u64>,
+        pub conflict: Option<String>,
+        pub fsync: Option<String>,
+        pub manifest: Option<String>,
+        pub limit: Option<String>,
+        pub read_limit: Option<String>,
     }
 
     impl Settings {
@@ -232,6 +423,17 @@ mod file_config {
                     settings.checksum = value.get("checksum").and_then(toml::Value::as_str).map(str::to_string);
                     settings.chunk_bytes = value.get("chunk-bytes").and_then(|v| v.as_integer()).map(|v| v as u64);
                     settings.write_timeout = value.get("write-timeout-sec").and_then(|v| v.as_integer()).map(|v| v as u64);
+                    settings.preserve = value.get("preserve").and_then(toml::Value::as_str).map(str::to_string);
+                    settings.symlinks = value.get("symlinks").and_then(toml::Value::as_str).map(str::to_string);
+                    settings.quick_check = value.get("quick-check").and_then(toml::Value::as_str).map(str::to_string);
+                    settings.delta = value.get("delta").and_then(toml::Value::as_bool);
+                    settings.mirror = value.get("mirror").and_then(toml::Value::as_bool);
+                    settings.max_deletions = value.get("max-deletions").and_then(|v| v.as_integer()).map(|v| v as u64);
+                    settings.conflict = value.get("conflict").and_then(toml::Value::as_str).map(str::to_string);
+                    settings.manifest = value.get("manifest").and_then(toml::Value::as_str).map(str::to_string);
+                    settings.fsync = value.get("fsync").and_then(toml::Value::as_str).map(str::to_string);
+                    settings.limit = value.get("limit").and_then(toml::Value::as_str).map(str::to_string);
+                    settings.read_limit = value.get("read-limit").and_then(toml::Value::as_str).map(str::to_string);
                 }
             }
 
@@ -239,3 +441,11 @@ mod file_config {
         }
     }
 }
+
+#[test]
+fn address_test() {
+    assert_eq!("[::1]:9099".parse::<Address>().unwrap(), Address::Tcp("[::1]:9099".parse().unwrap()));
+    assert_eq!("unix:/run/atilink.sock".parse::<Address>().unwrap(), Address::Unix(path::PathBuf::from("/run/atilink.sock")));
+    assert!("unix:".parse::<Address>().is_err());
+    assert!("localhost".parse::<Address>().is_err());
+}
@@ -2,7 +2,8 @@ mod conf;
 
 use std::{net::TcpStream, time::Instant};
 
-use conf::fetch_conf;
+use commons::{quick_check::{Action, Offer}, transport::Transport};
+use conf::{fetch_conf, Address};
 
 /// method to load the configuration and initialize the link
 pub fn init() -> Result<(), Box<dyn std::error::Error>> {
@@ -16,11 +17,12 @@ pub fn init() -> Result<(), Box<dyn std::error::Error>> {
     let sink = conf.sink()?;
     let role = conf.role()?;
 
-    let stream = TcpStream::connect(socket).inspect_err(|e| eprintln!("cannot connect to receiver {0}. {1}", socket, e))?;
+    let stream = connect(&socket).inspect_err(|e| eprintln!("cannot connect to receiver {0}. {1}", socket, e))?;
     stream.set_write_timeout(conf.write_timeout).inspect_err(|e| eprintln!("error setting timeout {e}"))?;
 
+    let mirror = conf.mirror();
     let instant = Instant::now();
-    let mut link = commons::connection::Link::new(stream).with_checksum(conf.checksum).with_compression(conf.compression);
+    let mut link = commons::connection::Link::new(stream).with_checksum(conf.checksum).with_compression(conf.compression).with_write_limit(conf.write_limit).with_read_limit(conf.read_limit).with_preserve(conf.preserve).with_symlinks(conf.symlinks).with_quick_check(conf.quick_check).with_delta(conf.delta).with_mirror(mirror).with_dry_run(conf.dry_run).with_conflict(conf.conflict).with_fsync(conf.fsync).with_manifest(conf.manifest).with_acks(true);
     link.write_role(role)?;
 
     match role {
@@ -33,39 +35,78 @@ pub fn init() -> Result<(), Box<dyn std::error::Error>> {
                     return error(commons::error::Error::DownloadError(s));
                 },
                 commons::Result::Marker { count, marker } => {
+                    let count = link.answer(&sink, count as usize)?;
+                    if let Some(listing) = link.listing() {
+                        println!("{} entries listed, {} files for {}", listing.entries.len(), listing.files(), human_size(listing.bytes()));
+                    }
                     for _ in 0..count {
                         link.read_from_stream(&sink, &marker)?;
                     }
+                    link.sync()?;
                     println!("{count} files read");
+
+                    if conf.dry_run || conf.conflict != commons::Conflict::Overwrite {
+                        report_plan(link.plan(), link.refusal());
+                    }
+
+                    // a refusal is reported along with the summary
+                    if mirror.is_some() {
+                        report_deletions(link.deletions(), conf.dry_run);
+                        let _ = link.mirror(&sink);
+                    }
+
+                    if let Some(report) = link.check_manifest(&sink)? {
+                        report_manifest(&report);
+                    }
+
+                    report_summary(&link.write_summary()?)?;
                 },
             }
         },
         commons::Role::Sink => {
+            // the source itself is always followed, the symlink policy applies to what is inside
+            let name = source.file_name().map(std::path::PathBuf::from).unwrap_or_default();
+            let source = match source.is_symlink() {
+                true => std::fs::canonicalize(&source).inspect_err(|e| eprintln!("broken symlink {}. {e}", source.display()))?,
+                false => source,
+            };
+
             if source.is_dir() {
                 tracing::debug!("source is a directory!");
-                let all_files = commons::get_recursive_paths(&source);
-                let len = all_files.len();
-                link.write_upload_metadata(all_files.len(), &sink)?;
-
-                for file in all_files {
-                    match file.strip_prefix(&source) {
-                        Ok(relative_path) => {
-                            link.write_to_stream(&file, &std::path::PathBuf::from(relative_path))?;
-                        },
-                        Err(e) => return error(commons::error::Error::InvalidRequest(format!("{} is not a relative path of {}, {e}", file.to_str().unwrap(), source.to_str().unwrap()))),
-                    }
+                let all_files = commons::relative_entries(&source, commons::get_recursive_paths(&source, conf.symlinks))?;
+                link.write_upload_metadata(all_files.len(), commons::total_size(&all_files, conf.symlinks), &sink)?;
+                read_acceptance(&mut link)?;
+
+                let entries = link.negotiate(all_files)?;
+                let len = entries.len();
+                for (file, relative_path) in entries {
+                    link.write_to_stream(&file, &relative_path)?;
                 }
 
                 println!("{} files uploaded", len);
+                if let Some(summary) = link.read_summary()? {
+                    report_summary(&summary)?;
+                }
             }
             else if source.is_file() {
                 println!("source is a file");
-                let rel = std::path::Path::new(source.file_name().unwrap());
-                link.write_upload_metadata(1, &sink)?;
-                link.write_to_stream(&source, rel)?;
+                link.write_upload_metadata(1, std::fs::metadata(&source)?.len(), &sink)?;
+                read_acceptance(&mut link)?;
+                for (file, relative_path) in link.negotiate(vec![(source, name)])? {
+                    link.write_to_stream(&file, &relative_path)?;
+                }
+                if let Some(summary) = link.read_summary()? {
+                    report_summary(&summary)?;
+                }
+            }
+
+            if conf.dry_run || conf.conflict != commons::Conflict::Overwrite {
+                report_plan(link.plan(), link.refusal());
             }
-            else if source.is_symlink() {
-               eprintln!("symlinks not supported");
+
+            // the server deletes once it has received everything, a refusal comes with the summary
+            if mirror.is_some() {
+                report_deletions(link.deletions(), conf.dry_run);
             }
         },
     }
@@ -76,6 +117,119 @@ pub fn init() -> Result<(), Box<dyn std::error::Error>> {
     Ok(())
 }
 
+/// fails when the server refuses the upload, for lack of space for instance
+fn read_acceptance<T: Transport>(link: &mut commons::connection::Link<T>) -> Result<(), commons::error::Error> {
+    match link.read_result()? {
+        commons::Result::Err(s) => {
+            eprintln!("upload refused: {s}");
+            Err(commons::error::Error::InvalidRequest(s))
+        },
+        commons::Result::Marker { .. } => Ok(()),
+    }
+}
+
+/// lists what the receiver does, or would do on a dry run, to every entry, with totals per action, and why it would refuse the transfer
+fn report_plan(plan: &[(Offer, Action)], refusal: Option<&str>) {
+    let mut totals = ["create", "overwrite", "skip", "rename", "fail"].map(|label| (label, 0, 0));
+    for (offer, action) in plan {
+        let size = offer.size.map_or("-".to_string(), human_size);
+        let path = match offer.rel_path.as_os_str().is_empty() {
+            true => std::path::Path::new("."),
+            false => offer.rel_path.as_path(),
+        };
+        match action {
+            Action::Rename { to } => println!("{:<9} {size:>10} {} as {}", label(action), path.display(), to.display()),
+            _ => println!("{:<9} {size:>10} {}", label(action), path.display()),
+        }
+
+        if let Some((_, count, bytes)) = totals.iter_mut().find(|(l, _, _)| *l == label(action)) {
+            *count += 1;
+            *bytes += offer.size.unwrap_or_default();
+        }
+    }
+
+    for (label, count, bytes) in totals.into_iter().filter(|(_, count, _)| *count > 0) {
+        println!("{count} entries to {label}, {}", human_size(bytes));
+    }
+    if let Some(reason) = refusal {
+        println!("the transfer would be refused, {reason}");
+    }
+}
+
+fn label(action: &Action) -> &'static str {
+    match action {
+        Action::Create => "create",
+        Action::Overwrite => "overwrite",
+        Action::Skip => "skip",
+        Action::Rename { .. } => "rename",
+        Action::Fail => "fail",
+    }
+}
+
+/// sizes in powers of 1000, as used across the README
+fn human_size(bytes: u64) -> String {
+    match bytes {
+        b if b >= 1000 * 1000 * 1000 => format!("{:.1}GB", b as f64 / 1e9),
+        b if b >= 1000 * 1000 => format!("{:.1}MB", b as f64 / 1e6),
+        b if b >= 1000 => format!("{:.1}KB", b as f64 / 1e3),
+        b => format!("{b}B"),
+    }
+}
+
+/// lists the entries mirroring removes from the destination
+fn report_deletions(deletions: &[std::path::PathBuf], dry_run: bool) {
+    for path in deletions {
+        println!("extraneous {}", path.display());
+    }
+    match dry_run {
+        true => println!("{} entries would be deleted", deletions.len()),
+        false => println!("{} entries to delete", deletions.len()),
+    }
+}
+
+/// lists the entries the receiver didn't write as sent, fails when any of them failed or mirroring was refused
+fn report_summary(summary: &commons::ack::Summary) -> Result<(), commons::error::Error> {
+    for ack in &summary.issues {
+        println!("{} {}", ack.rel_path.display(), ack.status);
+    }
+    println!("{} entries received, {} not", summary.ok, summary.issues.len());
+    // spent by the receiver, the server on uploads
+    println!("Time syncing: {:?}", summary.sync_time);
+
+    if let Some(reason) = &summary.mirror_refused {
+        eprintln!("nothing deleted, {reason}");
+    }
+    match (summary.failures().count(), &summary.mirror_refused) {
+        (0, None) => Ok(()),
+        (0, Some(reason)) => Err(commons::error::Error::InvalidRequest(reason.clone())),
+        (failed, _) => {
+            eprintln!("{failed} entries failed");
+            Err(commons::error::Error::InvalidRequest(format!("{failed} entries failed")))
+        },
+    }
+}
+
+/// lists how the destination differs from the manifest
+fn report_manifest(report: &commons::manifest::Report) {
+    for (label, paths) in [("missing", &report.missing), ("mismatched", &report.mismatched), ("extra", &report.extra)] {
+        for path in paths {
+            println!("{label:<10} {}", path.display());
+        }
+    }
+    println!("manifest checked, {} missing, {} mismatched, {} extra", report.missing.len(), report.mismatched.len(), report.extra.len());
+}
+
+/// method to open a transport to the server
+fn connect(address: &Address) -> std::io::Result<Box<dyn Transport>> {
+    match address {
+        Address::Tcp(socket) => Ok(Box::new(TcpStream::connect(socket)?)),
+        #[cfg(unix)]
+        Address::Unix(path) => Ok(Box::new(std::os::unix::net::UnixStream::connect(path)?)),
+        #[cfg(not(unix))]
+        Address::Unix(_) => Err(std::io::ErrorKind::Unsupported.into()),
+    }
+}
+
 fn error(error: commons::error::Error) -> Result<(), Box<dyn std::error::Error>> {
     Err(Box::new(error))
 }
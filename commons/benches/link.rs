@@ -0,0 +1,154 @@
+//! Per chunk cost of a [`Link`], a scaled down stand-in for the 21Gb case in the README.
+//! The transports used here don't touch the network, so only the work done by the link is measured.
+//! Run with `cargo bench -p commons --bench link`.
+
+use std::{io::{self, Read, Write}, path::Path, sync::Arc, time::Duration};
+use commons::{compression::{Compression, Zlib}, connection::Link, transport::Transport};
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+
+const FILE_SIZE: usize = 64 * 1000 * 1000;
+
+/// Discards everything written to it
+struct Discard;
+
+impl Read for Discard {
+    fn read(&mut self, _buf: &mut [u8]) -> io::Result<usize> {
+        Ok(0)
+    }
+}
+
+impl Write for Discard {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Transport for Discard {
+    fn set_read_timeout(&self, _timeout: Option<Duration>) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn set_write_timeout(&self, _timeout: Option<Duration>) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn shutdown(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Keeps everything written to it
+#[derive(Default)]
+struct Record(Vec<u8>);
+
+impl Read for Record {
+    fn read(&mut self, _buf: &mut [u8]) -> io::Result<usize> {
+        Ok(0)
+    }
+}
+
+impl Write for Record {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Transport for Record {
+    fn set_read_timeout(&self, _timeout: Option<Duration>) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn set_write_timeout(&self, _timeout: Option<Duration>) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn shutdown(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Plays back a recorded session
+struct Replay(io::Cursor<Arc<[u8]>>);
+
+impl Read for Replay {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.0.read(buf)
+    }
+}
+
+impl Write for Replay {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Transport for Replay {
+    fn set_read_timeout(&self, _timeout: Option<Duration>) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn set_write_timeout(&self, _timeout: Option<Duration>) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn shutdown(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+fn compression(name: &str) -> Option<Box<dyn Compression>> {
+    match name {
+        "Zlib" => Some(Box::new(Zlib)),
+        _ => None,
+    }
+}
+
+fn link(c: &mut Criterion) {
+    let dir = tempfile::tempdir().unwrap();
+    let source = dir.path().join("source.bin");
+    // half repeating, half noise, so compression has some work to do
+    let content = (0..FILE_SIZE).map(|i| if i.is_multiple_of(2) { (i % 251) as u8 } else { (i.wrapping_mul(2654435761) >> 13) as u8 }).collect::<Vec<u8>>();
+    std::fs::write(&source, content).unwrap();
+    let sink = dir.path().join("sink");
+
+    let mut group = c.benchmark_group("link");
+    group.sample_size(20).throughput(Throughput::Bytes(FILE_SIZE as u64));
+
+    for name in ["None", "Zlib"] {
+        group.bench_with_input(BenchmarkId::new("upstream", name), name, |b, name| {
+            let mut link = Link::new(Discard).with_compression(compression(name));
+            b.iter(|| link.write_to_stream(&source, Path::new("file.bin")).unwrap());
+        });
+
+        let mut sender = Link::new(Record::default()).with_compression(compression(name));
+        sender.write_upload_metadata(1, FILE_SIZE as u64, &sink).unwrap();
+        sender.write_to_stream(&source, Path::new("file.bin")).unwrap();
+        let recording: Arc<[u8]> = sender.into_inner().0.into();
+
+        group.bench_with_input(BenchmarkId::new("downstream", name), name, |b, _| {
+            b.iter(|| {
+                let mut link = Link::new(Replay(io::Cursor::new(recording.clone())));
+                let metadata = link.read_upload_metadata().unwrap();
+                link.read_from_stream(&metadata.destination, &metadata.eof_marker).unwrap();
+            });
+        });
+    }
+
+    group.finish();
+}
+
+criterion_group!(benches, link);
+criterion_main!(benches);
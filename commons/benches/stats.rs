@@ -0,0 +1,169 @@
+//! Reproducible version of the stats table in the README.
+//! Every row sends a file through a pair of [`Link`]s over an in-memory [`pipe`](commons::transport::pipe),
+//! once measuring wall time (throughput) and once measuring the CPU time of the whole process (both ends).
+//! Run with `cargo bench -p commons --bench stats`.
+//! The file size defaults to 4Mb and can be changed with `ATILINK_BENCH_BYTES`.
+
+use std::{path::Path, time::Duration};
+use commons::{checksum::{self, Checksum}, compression::{self, Compression}, connection::Link, transport::pipe};
+use criterion::{criterion_group, criterion_main, measurement::{Measurement, ValueFormatter}, BenchmarkId, Criterion, Throughput};
+
+/// (compression, checksum, chunk size) as listed in the README
+const ROWS: [(&str, &str, usize); 14] = [
+    ("None", "None", 1000 * 1000),
+    ("None", "None", 500 * 1000),
+    ("None", "None", 100 * 1000),
+    ("None", "None", 50 * 1000),
+    ("None", "None", 1000),
+    ("None", "None", 100),
+    ("None", "None", 10),
+    ("GZip", "None", 1000 * 1000),
+    ("GZip", "None", 100 * 1000),
+    ("GZip", "None", 10),
+    ("None", "Sha256", 1000 * 1000),
+    ("None", "Sha256", 100 * 1000),
+    ("None", "Sha256", 10),
+    ("Zlib", "Sha256", 1000 * 1000),
+];
+
+fn compression(name: &str) -> Option<Box<dyn Compression>> {
+    match name {
+        "GZip" => Some(Box::new(compression::GZip)),
+        "Zlib" => Some(Box::new(compression::Zlib)),
+        _ => None,
+    }
+}
+
+fn checksum(name: &str) -> Option<Box<dyn Checksum>> {
+    match name {
+        "Sha256" => Some(Box::new(checksum::Sha256)),
+        "Md5" => Some(Box::new(checksum::Md5)),
+        _ => None,
+    }
+}
+
+fn chunk_label(chunk: usize) -> String {
+    match chunk {
+        c if c >= 1000 * 1000 => format!("{}mb", c / (1000 * 1000)),
+        c if c >= 1000 => format!("{}kb", c / 1000),
+        c => format!("{c}b"),
+    }
+}
+
+/// sends `source` from one end of a pipe and receives it into `sink` on the other
+fn transfer(source: &Path, sink: &Path, row: (&str, &str, usize)) {
+    let (compression_name, checksum_name, chunk) = row;
+    let (left, right) = pipe();
+
+    std::thread::scope(|s| {
+        s.spawn(|| {
+            let mut link = Link::new(left).with_compression(compression(compression_name)).with_checksum(checksum(checksum_name)).with_chunk_size(chunk);
+            link.write_upload_metadata(1, 0, sink).unwrap();
+            link.write_to_stream(source, Path::new("file.bin")).unwrap();
+        });
+
+        let mut link = Link::new(right);
+        let metadata = link.read_upload_metadata().unwrap();
+        link.read_from_stream(&metadata.destination, &metadata.eof_marker).unwrap();
+    });
+}
+
+fn stats<M: Measurement>(c: &mut Criterion<M>, measurement: &str) {
+    let bytes = std::env::var("ATILINK_BENCH_BYTES").ok().and_then(|b| b.parse().ok()).unwrap_or(4 * 1000 * 1000);
+    let dir = tempfile::tempdir().unwrap();
+    let source = dir.path().join("source.bin");
+    // half repeating, half noise, so compression has some work to do
+    let content = (0..bytes).map(|i: usize| if i.is_multiple_of(2) { (i % 251) as u8 } else { (i.wrapping_mul(2654435761) >> 13) as u8 }).collect::<Vec<u8>>();
+    std::fs::write(&source, content).unwrap();
+    let sink = dir.path().join("sink");
+
+    let mut group = c.benchmark_group(format!("stats/{measurement}"));
+    group.sample_size(10).measurement_time(Duration::from_secs(10)).throughput(Throughput::Bytes(bytes as u64));
+
+    for (i, row) in ROWS.into_iter().enumerate() {
+        let (compression_name, checksum_name, chunk) = row;
+        let id = BenchmarkId::from_parameter(format!("{}-{compression_name}-{checksum_name}-{}", i + 1, chunk_label(chunk)));
+        group.bench_function(id, |b| b.iter(|| transfer(&source, &sink, row)));
+    }
+
+    group.finish();
+}
+
+fn wall(c: &mut Criterion) {
+    stats(c, "wall");
+}
+
+fn cpu(c: &mut Criterion<CpuTime>) {
+    stats(c, "cpu");
+}
+
+/// CPU time consumed by the whole process, i.e. both ends of the link
+struct CpuTime;
+
+impl CpuTime {
+    fn now() -> Duration {
+        let mut time = libc::timespec { tv_sec: 0, tv_nsec: 0 };
+        // SAFETY: `time` is a valid timespec for the duration of the call
+        unsafe { libc::clock_gettime(libc::CLOCK_PROCESS_CPUTIME_ID, &mut time) };
+        Duration::new(time.tv_sec as u64, time.tv_nsec as u32)
+    }
+}
+
+impl Measurement for CpuTime {
+    type Intermediate = Duration;
+    type Value = Duration;
+
+    fn start(&self) -> Self::Intermediate {
+        Self::now()
+    }
+
+    fn end(&self, i: Self::Intermediate) -> Self::Value {
+        Self::now() - i
+    }
+
+    fn add(&self, v1: &Self::Value, v2: &Self::Value) -> Self::Value {
+        *v1 + *v2
+    }
+
+    fn zero(&self) -> Self::Value {
+        Duration::ZERO
+    }
+
+    fn to_f64(&self, value: &Self::Value) -> f64 {
+        value.as_nanos() as f64
+    }
+
+    fn formatter(&self) -> &dyn ValueFormatter {
+        &CpuTimeFormatter
+    }
+}
+
+/// Formats CPU nanoseconds as milliseconds and throughput as MB per CPU second
+struct CpuTimeFormatter;
+
+impl ValueFormatter for CpuTimeFormatter {
+    fn scale_values(&self, _typical_value: f64, values: &mut [f64]) -> &'static str {
+        values.iter_mut().for_each(|v| *v /= 1e6);
+        "ms cpu"
+    }
+
+    fn scale_throughputs(&self, _typical_value: f64, throughput: &Throughput, values: &mut [f64]) -> &'static str {
+        let units = match throughput {
+            Throughput::Bytes(b) | Throughput::BytesDecimal(b) | Throughput::Elements(b) => *b as f64,
+        };
+        values.iter_mut().for_each(|v| *v = units * 1e9 / *v / 1e6);
+        "MB/cpu s"
+    }
+
+    fn scale_for_machines(&self, _values: &mut [f64]) -> &'static str {
+        "ns"
+    }
+}
+
+criterion_group!(wall_time, wall);
+criterion_group! {
+    name = cpu_time;
+    config = Criterion::default().with_measurement(CpuTime);
+    targets = cpu
+}
+criterion_main!(wall_time, cpu_time);
@@ -0,0 +1,88 @@
+use std::{path::PathBuf, time::Duration};
+use super::error::Error;
+
+/// How the receiver did with an entry
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub enum Status {
+    Ok,
+    /// a chunk failed its checksum or couldn't be decompressed, the entry is dropped
+    ChecksumFailed(String),
+    /// the entry couldn't be written, the reason is given
+    IoError(String),
+    /// the entry would go over the quota of the share, the reason tells by how much
+    QuotaExceeded(String),
+    /// deliberately not written, like links escaping the destination
+    Skipped(String),
+    /// written, but some of its attributes couldn't be set, which and why is given
+    AttributesNotSet(String),
+}
+
+impl Status {
+    /// outcome of writing an entry, checksum failures told apart from the rest
+    pub fn of(result: Result<(), Error>) -> Self {
+        match result {
+            Ok(()) => Self::Ok,
+            Err(Error::IntegrityError(e)) => Self::ChecksumFailed(e),
+            Err(e) => Self::IoError(e.to_string()),
+        }
+    }
+
+    pub fn is_failure(&self) -> bool {
+        matches!(self, Self::ChecksumFailed(_) | Self::IoError(_) | Self::QuotaExceeded(_))
+    }
+}
+
+impl std::fmt::Display for Status {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Ok => write!(f, "ok"),
+            Self::ChecksumFailed(e) => write!(f, "checksum failed, {e}"),
+            Self::IoError(e) => write!(f, "io error, {e}"),
+            Self::QuotaExceeded(e) => write!(f, "{e}"),
+            Self::Skipped(e) => write!(f, "skipped, {e}"),
+            Self::AttributesNotSet(e) => write!(f, "attributes not set, {e}"),
+        }
+    }
+}
+
+/// Sent by the receiver once it is done with an entry
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct Ack {
+    pub rel_path: PathBuf,
+    pub status: Status,
+}
+
+/// Sent by the receiver once the session is done
+#[derive(Debug, Default, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct Summary {
+    /// entries written as sent
+    pub ok: usize,
+    /// every other entry
+    pub issues: Vec<Ack>,
+    /// spent by the receiver syncing to disk
+    pub sync_time: Duration,
+    /// why the receiver deleted nothing when asked to mirror
+    pub mirror_refused: Option<String>,
+}
+
+impl Summary {
+    pub fn new(acks: &[Ack], sync_time: Duration) -> Self {
+        Self {
+            ok: acks.iter().filter(|ack| ack.status == Status::Ok).count(),
+            issues: acks.iter().filter(|ack| ack.status != Status::Ok).cloned().collect(),
+            sync_time,
+            mirror_refused: None,
+        }
+    }
+
+    pub fn with_mirror_refused(self, mirror_refused: Option<String>) -> Self {
+        Self {
+            mirror_refused,
+            ..self
+        }
+    }
+
+    pub fn failures(&self) -> impl Iterator<Item = &Ack> {
+        self.issues.iter().filter(|ack| ack.status.is_failure())
+    }
+}
@@ -0,0 +1,643 @@
+use std::{path, time::{Duration, Instant}};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncSeekExt, AsyncWrite, AsyncWriteExt};
+use crate::{ack::{Ack, Status, Summary}, connection::{confined, entry_failure, link_escapes, pack, temp_path, unpack, Buffers}, Abort, DownloadMetadata, EntryKind, Role, UploadMetadata};
+
+use super::{ABORT_FRAME, CHUNK, EOF_MARKER, checksum, compression, error, FileMetadata};
+
+/// Async counterpart of [`Link`](crate::connection::Link).
+/// Works over any tokio [`AsyncRead`] + [`AsyncWrite`] stream and speaks the same wire format,
+/// so an [`AsyncLink`] can talk to a blocking [`Link`](crate::connection::Link) on the other end.
+pub struct AsyncLink<S> {
+    stream: S,
+    compression: Option<Box<dyn compression::Compression>>,
+    checksum: Option<Box<dyn checksum::Checksum>>,
+    /// refuse incoming entries landing outside of the destination, see [`Link`](crate::connection::Link)
+    confine_links: bool,
+    /// acknowledge every entry received and sum up the session, see [`Link`](crate::connection::Link)
+    acks: bool,
+    /// how every entry went, as acknowledged by the receiver
+    outcomes: Vec<Ack>,
+    /// spent syncing received files to disk
+    sync_time: Duration,
+    buffers: Buffers,
+}
+
+impl<S: AsyncRead + AsyncWrite + Unpin> AsyncLink<S> {
+    pub fn new(stream: S) -> Self {
+        Self {
+            stream,
+            compression: None,
+            checksum: None,
+            confine_links: false,
+            acks: false,
+            outcomes: Vec::new(),
+            sync_time: Duration::ZERO,
+            buffers: Buffers::default(),
+        }
+    }
+
+    pub fn with_compression(self, compression: Option<Box<dyn compression::Compression>>) -> Self {
+        if compression.is_none() {
+            tracing::info!("COMPRESSION is NONE");
+        }
+        Self {
+            compression,
+            ..self
+        }
+    }
+
+    pub fn with_checksum(self, checksum: Option<Box<dyn checksum::Checksum>>) -> Self {
+        if checksum.is_none() {
+            tracing::info!("CHECKSUM is NONE");
+        }
+        Self {
+            checksum,
+            ..self
+        }
+    }
+
+    /// refuses incoming links whose target escapes the destination, meant for servers
+    pub fn with_confined_links(self, confine_links: bool) -> Self {
+        Self {
+            confine_links,
+            ..self
+        }
+    }
+
+    /// asks the receiver to acknowledge every entry and sum up the session
+    pub fn with_acks(self, acks: bool) -> Self {
+        Self {
+            acks,
+            ..self
+        }
+    }
+
+    /// how every entry went so far
+    pub fn outcomes(&self) -> &[Ack] {
+        &self.outcomes
+    }
+
+    /// returns the underlying stream
+    pub fn into_inner(self) -> S {
+        self.stream
+    }
+}
+
+/// Methods aimed for reading from stream
+impl<S: AsyncRead + AsyncWrite + Unpin> AsyncLink<S> {
+    /// receives the next entry into `destination`. The entry failing is recorded and acknowledged when asked for, only the session failing is an error.
+    pub async fn read_from_stream(&mut self, destination: &path::Path, marker: &[u8]) -> Result<(), error::Error> {
+        tracing::info!("reading file metadata");
+        let file_metadata = self.read_file_metadata().await?;
+        let path = destination.join(&file_metadata.rel_path);
+
+        let escapes = self.confine_links && confined(destination, &path, file_metadata.kind == EntryKind::Directory).is_none();
+        let status = match &file_metadata.kind {
+            _ if escapes => self.skip(&file_metadata, marker, Status::Skipped(format!("{} resolves outside of the destination", path.display()))).await?,
+            EntryKind::Directory => {
+                tracing::info!("creating directory {}", path.display());
+                Status::of(tokio::fs::create_dir_all(&path).await.map_err(error::Error::from))
+            },
+            EntryKind::Symlink { target } if self.confine_links && link_escapes(destination, &path, target) => {
+                Status::Skipped(format!("link to {} escapes the destination", target.display()))
+            },
+            EntryKind::Symlink { target } => {
+                tracing::info!("creating link {} to {}", path.display(), target.display());
+                #[cfg(unix)]
+                let created = create_link(&path, tokio::fs::symlink(target, &path)).await;
+                #[cfg(not(unix))]
+                let created = {
+                    tracing::warn!("links not supported on this platform, skipping {}", path.display());
+                    Ok(())
+                };
+                Status::of(created)
+            },
+            EntryKind::HardLink { target } if self.confine_links && (crate::escapes_tree(path::Path::new(""), target) || confined(destination, &destination.join(target), false).is_none()) => {
+                Status::Skipped(format!("hard link to {} escapes the destination", target.display()))
+            },
+            EntryKind::HardLink { target } => {
+                tracing::info!("creating hard link {} to {}", path.display(), target.display());
+                Status::of(create_link(&path, tokio::fs::hard_link(destination.join(target), &path)).await)
+            },
+            EntryKind::File => self.read_file(&path, &file_metadata, marker).await?,
+        };
+
+        if status.is_failure() {
+            tracing::error!("{} not received, {status}", path.display());
+        }
+        else if status != Status::Ok {
+            tracing::warn!("{} {status}", path.display());
+        }
+        let ack = Ack { rel_path: file_metadata.rel_path, status };
+        if self.acks {
+            self.upstream(&bincode::serialize(&ack).unwrap()).await?;
+            self.stream.flush().await?;
+        }
+        self.outcomes.push(ack);
+
+        Ok(())
+    }
+
+    /// receives the content of a regular file, written aside and swapped in once complete as `Link` does.
+    /// A file that can't be written is read to its end all the same.
+    async fn read_file(&mut self, path: &path::Path, file_metadata: &FileMetadata, marker: &[u8]) -> Result<Status, error::Error> {
+        let temp = temp_path(path);
+        let created = match path.parent() {
+            Some(parent) => tokio::fs::create_dir_all(parent).await,
+            None => Ok(()),
+        };
+        let created = match created {
+            Ok(()) => tokio::fs::File::create(&temp).await,
+            Err(e) => Err(e),
+        };
+        let mut file = match created {
+            Ok(file) => file,
+            Err(e) => return self.skip(file_metadata, marker, Status::of(Err(e.into()))).await,
+        };
+        let mut placement = file_metadata.extents.as_ref().map(crate::sparse::Extents::placement);
+        // the first error is kept, the rest of the file is only read
+        let mut failure = None;
+
+        loop {
+            if let Err(e) = self.read_chunk().await {
+                tracing::error!("error receiving {e}. Deleting file at {}", temp.display());
+                tokio::fs::remove_file(&temp).await?;
+                return entry_failure(e);
+            }
+
+            let written = match self.unpack_chunk() {
+                Ok(buffer) if buffer == marker => break,
+                Ok(_) if failure.is_some() => continue,
+                Ok(buffer) => match placement.as_mut() {
+                    Some(placement) => write_sparse(&mut file, placement, buffer).await,
+                    None => file.write_all(buffer).await.map_err(error::Error::from),
+                },
+                Err(e) => Err(e),
+            };
+
+            if let Err(e) = written {
+                failure.get_or_insert(e);
+            }
+        }
+
+        if let Some(e) = failure {
+            tracing::error!("error receiving {e}. Deleting file at {}", temp.display());
+            tokio::fs::remove_file(&temp).await?;
+            return Ok(Status::of(Err(e)));
+        }
+
+        tracing::info!("reached end of file");
+        if let Some(extents) = &file_metadata.extents {
+            file.set_len(extents.size).await?;
+        }
+        let instant = Instant::now();
+        file.sync_all().await?;
+        self.sync_time += instant.elapsed();
+        tokio::fs::rename(&temp, path).await?;
+        Ok(Status::Ok)
+    }
+
+    /// sums up the entries received so far and sends it to the sender when acknowledging, to be called once the session is done
+    pub async fn write_summary(&mut self) -> Result<Summary, error::Error> {
+        let summary = Summary::new(&self.outcomes, self.sync_time);
+        if self.acks {
+            self.upstream(&bincode::serialize(&summary).unwrap()).await?;
+            self.stream.flush().await?;
+        }
+        Ok(summary)
+    }
+
+    /// reads a refused entry to its end without writing it, it ends with `status`
+    async fn skip(&mut self, file_metadata: &FileMetadata, marker: &[u8], status: Status) -> Result<Status, error::Error> {
+        if file_metadata.kind != EntryKind::File {
+            return Ok(status);
+        }
+        loop {
+            match self.read_chunk().await {
+                // the sender gave up on it as well
+                Err(error::Error::Aborted(Abort::Entry(_))) => return Ok(status),
+                Err(e) => return Err(e),
+                Ok(()) if self.unpack_chunk().is_ok_and(|buffer| buffer == marker) => return Ok(status),
+                Ok(()) => {},
+            }
+        }
+    }
+
+    /// method to read an incoming chunk.
+    /// See [`Link`](crate::connection::Link) for the layout.
+    async fn downstream(&mut self) -> Result<&[u8], error::Error> {
+        self.read_chunk().await?;
+        self.unpack_chunk()
+    }
+
+    /// reads the next chunk as it is on the wire, only failing when the stream does or the sender aborts
+    async fn read_chunk(&mut self) -> Result<(), error::Error> {
+        let mut len = self.read_len().await?;
+        if len == ABORT_FRAME {
+            let abort = bincode::deserialize(&self.read_raw().await?).map_err(|e| error::Error::InvalidRequest(format!("invalid abort {e}")))?;
+            tracing::error!("{abort}");
+            return Err(error::Error::Aborted(abort));
+        }
+
+        if self.checksum.is_some() {
+            let checksum_len = len;
+            tracing::debug!("read checksum len: {}", checksum_len);
+            self.buffers.checksum.resize(checksum_len as usize, 0);
+            self.stream.read_exact(&mut self.buffers.checksum).await?;
+            len = self.read_len().await?;
+        }
+
+        tracing::info!("reading bytes: {}", len);
+        self.buffers.wire.resize(len as usize, 0);
+        self.stream.read_exact(&mut self.buffers.wire).await?;
+        Ok(())
+    }
+
+    /// checks and decompresses the chunk last read
+    fn unpack_chunk(&mut self) -> Result<&[u8], error::Error> {
+        let checksum = match self.checksum.is_some() {
+            true => std::str::from_utf8(&self.buffers.checksum).ok(),
+            false => None,
+        };
+        unpack(self.compression.as_deref(), self.checksum.as_deref(), checksum, &self.buffers.wire, &mut self.buffers.chunk)
+    }
+
+    /// Method to read the first 4 bytes of a stream.
+    /// Used to determine the length of the incoming message
+    async fn read_len(&mut self) -> Result<u32, error::Error> {
+        let mut len_buf = [0; 4];
+        self.stream.read_exact(&mut len_buf).await?;
+        Ok(u32::from_be_bytes(len_buf))
+    }
+
+    /// reads a length prefixed message without checksum or compression
+    async fn read_raw(&mut self) -> Result<Vec<u8>, error::Error> {
+        let len = self.read_len().await?;
+        let mut buffer = vec![0; len as usize];
+        self.stream.read_exact(&mut buffer).await?;
+        Ok(buffer)
+    }
+
+    /// reader for [`DownloadMetadata`]
+    pub async fn read_download_metadata(&mut self) -> Result<DownloadMetadata, error::Error> {
+        let download_metadata = DownloadMetadata::from_bytes(&self.read_raw().await?);
+
+        tracing::debug!("received download metadata: {:?}", download_metadata);
+        negotiable(download_metadata.quick_check.is_some() || download_metadata.mirror.is_some() || download_metadata.dry_run || download_metadata.conflict != crate::Conflict::Overwrite || download_metadata.manifest.is_some(), download_metadata.delta)?;
+        self.compression = download_metadata.compression.as_ref().map(|c| c.get_algo());
+        self.checksum = download_metadata.checksum.as_ref().map(|c| c.get_algo());
+        self.acks = download_metadata.acks;
+
+        Ok(download_metadata)
+    }
+
+    /// method to read role from a stream
+    pub async fn read_role(&mut self) -> Result<Role, error::Error> {
+        Ok(Role::from_bytes(&self.read_raw().await?))
+    }
+
+    pub async fn read_result(&mut self) -> Result<super::Result, error::Error> {
+        let bytes = self.downstream().await?;
+        Ok(super::Result::from_bytes(bytes))
+    }
+
+    async fn read_file_metadata(&mut self) -> Result<FileMetadata, error::Error> {
+        let bytes = self.downstream().await?;
+        Ok(FileMetadata::from_bytes(bytes))
+    }
+
+    /// raw read
+    pub async fn read_upload_metadata(&mut self) -> Result<UploadMetadata, error::Error> {
+        let upload_metadata = UploadMetadata::from_bytes(&self.read_raw().await?);
+        negotiable(upload_metadata.quick_check.is_some() || upload_metadata.mirror.is_some() || upload_metadata.dry_run || upload_metadata.conflict != crate::Conflict::Overwrite || upload_metadata.manifest.is_some(), upload_metadata.delta)?;
+
+        self.compression = upload_metadata.compression.as_ref().map(|c| c.get_algo());
+        self.checksum = upload_metadata.checksum.as_ref().map(|c| c.get_algo());
+        self.acks = upload_metadata.acks;
+
+        Ok(upload_metadata)
+    }
+}
+
+/// offers, manifests and deltas are exchanged before or within entries, which [`AsyncLink`] doesn't do yet
+fn negotiable(negotiation: bool, delta: bool) -> Result<(), error::Error> {
+    match negotiation || delta {
+        true => Err(error::Error::invalid_request("quick checks, mirroring, dry runs, conflict policies, manifests and delta transfers are not supported by AsyncLink")),
+        false => Ok(()),
+    }
+}
+
+/// Methods aimed for writing to stream
+impl<S: AsyncRead + AsyncWrite + Unpin> AsyncLink<S> {
+    /// sends the entry at `source` as `relative_path` and waits for its acknowledgement when asked for
+    pub async fn write_to_stream(&mut self, source: &path::Path, relative_path: &path::Path) -> Result<(), error::Error> {
+        self.send_entry(source, relative_path).await?;
+        if !self.acks {
+            return Ok(());
+        }
+
+        let ack: Ack = bincode::deserialize(self.downstream().await?).map_err(|e| error::Error::InvalidRequest(format!("invalid acknowledgement {e}")))?;
+        if ack.status.is_failure() {
+            tracing::error!("{} not received, {}", relative_path.display(), ack.status);
+        }
+        else if ack.status != Status::Ok {
+            tracing::warn!("{} {}", relative_path.display(), ack.status);
+        }
+        self.outcomes.push(ack);
+        Ok(())
+    }
+
+    /// reads the summary of the receiver once every entry is sent, `None` without acknowledgements
+    pub async fn read_summary(&mut self) -> Result<Option<Summary>, error::Error> {
+        if !self.acks {
+            return Ok(None);
+        }
+
+        let summary = bincode::deserialize(self.downstream().await?).map_err(|e| error::Error::InvalidRequest(format!("invalid summary {e}")))?;
+        Ok(Some(summary))
+    }
+
+    async fn send_entry(&mut self, source: &path::Path, relative_path: &path::Path) -> Result<(), error::Error> {
+        if tokio::fs::symlink_metadata(source).await.is_ok_and(|metadata| metadata.is_dir()) {
+            tracing::info!("sending directory {}", relative_path.display());
+            let file_metadata = FileMetadata::new(relative_path).with_kind(EntryKind::Directory);
+            self.upstream(&file_metadata.to_bytes()).await?;
+            self.stream.flush().await?;
+            return Ok(());
+        }
+
+        // a file that can't be read is announced all the same and aborted, so the receiver keeps count
+        self.write_file_metadata(relative_path).await?;
+
+        let mut file = match tokio::fs::File::open(source).await {
+            Ok(file) => file,
+            Err(e) => return self.abort_entry(relative_path, e).await,
+        };
+        let mut buffer = vec![0; CHUNK];
+
+        loop {
+            let bytes_read = match file.read(&mut buffer).await {
+                Ok(bytes_read) => bytes_read,
+                Err(e) => return self.abort_entry(relative_path, e).await,
+            };
+            if bytes_read == 0 {
+                tracing::info!("reached end of file");
+                self.upstream(EOF_MARKER.as_ref()).await?;
+                break;
+            }
+
+            self.upstream(&buffer[..bytes_read]).await?;
+        }
+
+        self.stream.flush().await?;
+        Ok(())
+    }
+
+    /// sends an [`Abort::Entry`] in place of the rest of the file.
+    /// See [`Link`](crate::connection::Link) for the layout.
+    async fn abort_entry(&mut self, relative_path: &path::Path, e: std::io::Error) -> Result<(), error::Error> {
+        tracing::error!("aborting {}. {e}", relative_path.display());
+        self.stream.write_all(&ABORT_FRAME.to_be_bytes()).await?;
+        write_raw(&mut self.stream, &bincode::serialize(&Abort::Entry(e.to_string())).unwrap()).await?;
+        self.stream.flush().await?;
+        Ok(())
+    }
+
+    /// method to send file metadata
+    pub async fn write_file_metadata(&mut self, relative_path: &path::Path) -> Result<(), error::Error> {
+        let file_metadata = FileMetadata::new(relative_path);
+        self.upstream(&file_metadata.to_bytes()).await
+    }
+
+    /// method to upload the chunk.
+    /// See [`Link`](crate::connection::Link) for the layout.
+    async fn upstream(&mut self, buffer: &[u8]) -> Result<(), error::Error> {
+        let (checksum, buffer) = pack(self.compression.as_deref(), self.checksum.as_deref(), buffer, &mut self.buffers.wire)?;
+
+        if let Some(checksum) = checksum {
+            tracing::debug!("writing checksum");
+            write_raw(&mut self.stream, checksum.as_bytes()).await?;
+        }
+
+        tracing::info!("writing buffer");
+        write_raw(&mut self.stream, buffer).await
+    }
+
+    /// writes a length prefixed message without checksum or compression
+    async fn write_raw(&mut self, bytes: &[u8]) -> Result<(), error::Error> {
+        write_raw(&mut self.stream, bytes).await
+    }
+
+    /// method to write the download metadata to the stream
+    pub async fn write_download_metadata(&mut self, destination: &path::Path) -> Result<(), error::Error> {
+        let download_metadata = DownloadMetadata::new(destination).with_compression(self.compression.as_ref().map(|c| c.get_type())).with_checksum(self.checksum.as_ref().map(|c| c.get_type())).with_acks(self.acks);
+        self.write_raw(&download_metadata.to_bytes()).await?;
+        self.stream.flush().await?;
+        Ok(())
+    }
+
+    /// method to assign role to the server
+    pub async fn write_role(&mut self, role: Role) -> Result<(), error::Error> {
+        self.write_raw(&role.to_bytes()).await?;
+        self.stream.flush().await?;
+        Ok(())
+    }
+
+    pub async fn write_err_result(&mut self, msg: String) -> Result<(), error::Error> {
+        let result = super::Result::Err(msg);
+        self.upstream(&result.to_bytes()).await?;
+        self.stream.flush().await?;
+        Ok(())
+    }
+
+    pub async fn write_ok_result(&mut self, count: usize) -> Result<(), error::Error> {
+        let result = super::Result::Marker { count: count as u32, marker: EOF_MARKER.to_vec() };
+        tracing::info!("writing ok result");
+        self.upstream(&result.to_bytes()).await?;
+        self.stream.flush().await?;
+        Ok(())
+    }
+
+    /// raw upload
+    pub async fn write_upload_metadata(&mut self, count: usize, bytes: u64, destination: &path::Path) -> Result<(), error::Error> {
+        let upload_metadata = UploadMetadata::new(count as u32, destination).with_bytes(bytes).with_compression(self.compression.as_ref().map(|c| c.get_type())).with_checksum(self.checksum.as_ref().map(|c| c.get_type())).with_acks(self.acks);
+        self.write_raw(&upload_metadata.to_bytes()).await?;
+        self.stream.flush().await?;
+        Ok(())
+    }
+}
+
+/// creates the parent of `path`, replaces whatever but a directory is there, then makes the link with `create`
+async fn create_link(path: &path::Path, create: impl std::future::Future<Output = std::io::Result<()>>) -> Result<(), error::Error> {
+    if let Some(parent) = path.parent() {
+        tokio::fs::create_dir_all(parent).await?;
+    }
+    if tokio::fs::symlink_metadata(path).await.is_ok_and(|m| !m.is_dir()) {
+        tokio::fs::remove_file(path).await?;
+    }
+    Ok(create.await?)
+}
+
+/// writes a chunk of a sparse file to the extents it belongs to
+async fn write_sparse(file: &mut tokio::fs::File, placement: &mut crate::sparse::Placement<'_>, mut buffer: &[u8]) -> Result<(), error::Error> {
+    while !buffer.is_empty() {
+        let (offset, n) = placement.next(buffer.len()).ok_or(error::Error::integrity_error("received more data than the extents of the file"))?;
+        file.seek(std::io::SeekFrom::Start(offset)).await?;
+        file.write_all(&buffer[..n]).await?;
+        buffer = &buffer[n..];
+    }
+    Ok(())
+}
+
+/// writes a length prefixed message
+async fn write_raw(stream: &mut (impl AsyncWrite + Unpin), bytes: &[u8]) -> Result<(), error::Error> {
+    let len = bytes.len() as u32;
+    stream.write_all(len.to_be_bytes().as_ref()).await?;
+    stream.write_all(bytes).await?;
+    Ok(())
+}
+
+#[tokio::test]
+async fn async_round_trip() {
+    let dir = tempfile::tempdir().unwrap();
+    let source = dir.path().join("source.bin");
+    let sink = dir.path().join("sink");
+    let content = (0..CHUNK * 2 + 17).map(|i| (i % 251) as u8).collect::<Vec<u8>>();
+    std::fs::write(&source, &content).unwrap();
+
+    let (client, server) = tokio::io::duplex(64 * 1024);
+    let mut sender = AsyncLink::new(client).with_compression(Some(Box::new(compression::GZip))).with_checksum(Some(Box::new(checksum::Md5))).with_acks(true);
+    let mut receiver = AsyncLink::new(server);
+
+    let send = async {
+        sender.write_upload_metadata(1, content.len() as u64, &sink).await.unwrap();
+        sender.write_to_stream(&source, path::Path::new("file.bin")).await.unwrap();
+        sender.read_summary().await.unwrap()
+    };
+    let receive = async {
+        let metadata = receiver.read_upload_metadata().await.unwrap();
+        receiver.read_from_stream(&metadata.destination, &metadata.eof_marker).await.unwrap();
+        receiver.write_summary().await.unwrap();
+    };
+    let (summary, ()) = tokio::join!(send, receive);
+
+    assert_eq!(sender.outcomes(), [Ack { rel_path: "file.bin".into(), status: Status::Ok }]);
+    assert_eq!(summary.map(|summary| summary.ok), Some(1));
+    assert_eq!(std::fs::read(sink.join("file.bin")).unwrap(), content);
+    assert!(!temp_path(&sink.join("file.bin")).exists());
+}
+
+#[tokio::test]
+async fn blocking_sender_async_receiver() {
+    let dir = tempfile::tempdir().unwrap();
+    let source = dir.path().join("source.bin");
+    let sink = dir.path().join("sink");
+    std::fs::write(&source, b"same wire format").unwrap();
+
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let address = listener.local_addr().unwrap();
+
+    let sender = std::thread::spawn({
+        let (source, sink) = (source.clone(), sink.clone());
+        move || {
+            let stream = std::net::TcpStream::connect(address).unwrap();
+            // acknowledged, as the client always asks
+            let mut link = crate::connection::Link::new(stream).with_checksum(Some(Box::new(checksum::Sha256))).with_acks(true);
+            link.write_upload_metadata(1, 0, &sink).unwrap();
+            link.write_to_stream(&source, path::Path::new("file.txt")).unwrap();
+            link.read_summary().unwrap()
+        }
+    });
+
+    let (stream, _) = listener.accept().await.unwrap();
+    let mut link = AsyncLink::new(stream);
+    let metadata = link.read_upload_metadata().await.unwrap();
+    link.read_from_stream(&metadata.destination, &metadata.eof_marker).await.unwrap();
+    link.write_summary().await.unwrap();
+
+    assert_eq!(sender.join().unwrap().map(|summary| summary.ok), Some(1));
+
+    assert_eq!(std::fs::read(sink.join("file.txt")).unwrap(), b"same wire format");
+}
+
+#[tokio::test]
+async fn corrupt_chunk() {
+    let dir = tempfile::tempdir().unwrap();
+    let sink = dir.path().join("sink");
+    for name in ["a.txt", "c.txt"] {
+        std::fs::write(dir.path().join(name), name).unwrap();
+    }
+
+    let (client, server) = tokio::io::duplex(64 * 1024);
+    let mut sender = AsyncLink::new(client).with_checksum(Some(Box::new(checksum::Md5))).with_acks(true);
+    let mut receiver = AsyncLink::new(server);
+
+    let send = async {
+        sender.write_upload_metadata(3, 0, &sink).await.unwrap();
+        sender.write_to_stream(&dir.path().join("a.txt"), path::Path::new("a.txt")).await.unwrap();
+
+        // a chunk failing its checksum, then the rest of the file
+        sender.write_file_metadata(path::Path::new("b.txt")).await.unwrap();
+        write_raw(&mut sender.stream, b"bogus").await.unwrap();
+        write_raw(&mut sender.stream, b"corrupt").await.unwrap();
+        sender.upstream(b"intact").await.unwrap();
+        sender.upstream(EOF_MARKER.as_ref()).await.unwrap();
+        sender.stream.flush().await.unwrap();
+        let ack: Ack = bincode::deserialize(sender.downstream().await.unwrap()).unwrap();
+
+        sender.write_to_stream(&dir.path().join("c.txt"), path::Path::new("c.txt")).await.unwrap();
+        (ack, sender.read_summary().await.unwrap().unwrap())
+    };
+    let receive = async {
+        let metadata = receiver.read_upload_metadata().await.unwrap();
+        for _ in 0..metadata.count {
+            receiver.read_from_stream(&metadata.destination, &metadata.eof_marker).await.unwrap();
+        }
+        receiver.write_summary().await.unwrap();
+    };
+    let ((ack, summary), ()) = tokio::join!(send, receive);
+
+    assert!(matches!(ack.status, Status::ChecksumFailed(_)));
+    assert_eq!(summary.ok, 2);
+    assert_eq!(summary.failures().map(|ack| ack.rel_path.clone()).collect::<Vec<_>>(), [path::PathBuf::from("b.txt")]);
+    assert_eq!(std::fs::read(sink.join("a.txt")).unwrap(), b"a.txt");
+    assert_eq!(std::fs::read(sink.join("c.txt")).unwrap(), b"c.txt");
+    assert!(!sink.join("b.txt").exists());
+    assert!(!temp_path(&sink.join("b.txt")).exists());
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn async_links_confined() {
+    let dir = tempfile::tempdir().unwrap();
+    let sink = dir.path().join("sink");
+    let outside = dir.path().join("outside");
+    std::fs::create_dir_all(&sink).unwrap();
+    std::fs::create_dir_all(&outside).unwrap();
+    std::os::unix::fs::symlink(&outside, sink.join("out")).unwrap();
+
+    let (client, server) = tokio::io::duplex(64 * 1024);
+    let mut sender = AsyncLink::new(client);
+    let mut receiver = AsyncLink::new(server).with_confined_links(true);
+
+    let send = async {
+        sender.write_upload_metadata(3, 1, &sink).await.unwrap();
+        sender.upstream(&FileMetadata::new(path::Path::new("escape")).with_kind(EntryKind::Symlink { target: "../outside".into() }).to_bytes()).await.unwrap();
+        sender.upstream(&FileMetadata::new(path::Path::new("hard")).with_kind(EntryKind::HardLink { target: "../outside/file".into() }).to_bytes()).await.unwrap();
+        sender.write_file_metadata(path::Path::new("out/x")).await.unwrap();
+        sender.upstream(b"x").await.unwrap();
+        sender.upstream(EOF_MARKER.as_ref()).await.unwrap();
+        sender.stream.flush().await.unwrap();
+    };
+    let receive = async {
+        let metadata = receiver.read_upload_metadata().await.unwrap();
+        for _ in 0..metadata.count {
+            receiver.read_from_stream(&metadata.destination, &metadata.eof_marker).await.unwrap();
+        }
+    };
+    tokio::join!(send, receive);
+
+    assert!(std::fs::symlink_metadata(sink.join("escape")).is_err());
+    assert!(std::fs::symlink_metadata(sink.join("hard")).is_err());
+    assert_eq!(std::fs::read_dir(&outside).unwrap().count(), 0);
+}
@@ -0,0 +1,100 @@
+use std::{fs, time::SystemTime};
+use super::{error::Error, Preserve};
+
+/// Attributes of a file sent along with its [`FileMetadata`](crate::FileMetadata).
+/// Only the attributes requested by [`Preserve`] are filled in by the sender.
+#[derive(Debug, Default, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct Attributes {
+    /// unix mode bits
+    pub mode: Option<u32>,
+    pub modified: Option<SystemTime>,
+    pub accessed: Option<SystemTime>,
+    /// (uid, gid)
+    pub owner: Option<(u32, u32)>,
+}
+
+impl Attributes {
+    /// collects the attributes requested by `preserve`
+    pub fn read(metadata: &fs::Metadata, preserve: &Preserve) -> Self {
+        let mut attributes = Self::default();
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::MetadataExt;
+
+            if preserve.mode {
+                attributes.mode = Some(metadata.mode() & 0o7777);
+            }
+
+            if preserve.owner {
+                attributes.owner = Some((metadata.uid(), metadata.gid()));
+            }
+        }
+
+        if preserve.times {
+            attributes.modified = metadata.modified().ok();
+            attributes.accessed = metadata.accessed().ok();
+        }
+
+        attributes
+    }
+
+    /// applies the attributes to a fully written file.
+    /// The owner is only changed when running as root, times are applied last so nothing bumps them afterwards.
+    pub fn apply(&self, file: &fs::File) -> Result<(), Error> {
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+
+            if let Some((uid, gid)) = self.owner {
+                // SAFETY: geteuid has no preconditions
+                match unsafe { libc::geteuid() } {
+                    0 => std::os::unix::fs::fchown(file, Some(uid), Some(gid))?,
+                    _ => tracing::debug!("not running as root, owner not preserved"),
+                }
+            }
+
+            if let Some(mode) = self.mode {
+                file.set_permissions(fs::Permissions::from_mode(mode))?;
+            }
+        }
+
+        if self.modified.is_some() || self.accessed.is_some() {
+            let mut times = fs::FileTimes::new();
+            if let Some(modified) = self.modified {
+                times = times.set_modified(modified);
+            }
+            if let Some(accessed) = self.accessed {
+                times = times.set_accessed(accessed);
+            }
+            file.set_times(times)?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(unix)]
+#[test]
+fn attributes_test() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let dir = tempfile::tempdir().unwrap();
+    let source = dir.path().join("source");
+    let sink = dir.path().join("sink");
+    fs::write(&source, b"source").unwrap();
+    fs::write(&sink, b"sink").unwrap();
+
+    let modified = SystemTime::UNIX_EPOCH + std::time::Duration::from_secs(1_000_000);
+    fs::File::options().write(true).open(&source).unwrap().set_modified(modified).unwrap();
+    fs::set_permissions(&source, fs::Permissions::from_mode(0o640)).unwrap();
+
+    let preserve = Preserve { mode: true, times: true, ..Preserve::default() };
+    let attributes = Attributes::read(&fs::metadata(&source).unwrap(), &preserve);
+    assert_eq!(attributes.owner, None);
+
+    attributes.apply(&fs::File::options().write(true).open(&sink).unwrap()).unwrap();
+    let metadata = fs::metadata(&sink).unwrap();
+    assert_eq!(metadata.permissions().mode() & 0o7777, 0o640);
+    assert_eq!(metadata.modified().unwrap(), modified);
+}
@@ -1,6 +1,6 @@
 use sha256::digest;
 
-pub trait Checksum {
+pub trait Checksum: Send + Sync {
     fn generate(&self, bytes: &[u8]) -> String;
     fn valdate(&self, bytes: &[u8], hash: &str) -> bool {
         self.generate(bytes) == hash
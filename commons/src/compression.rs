@@ -3,24 +3,39 @@ use super::error::Error;
 
 use flate2::{read::{GzDecoder, ZlibDecoder}, write::{GzEncoder, ZlibEncoder}, Compression as Comp};
 
-pub trait Compression {
-    fn compress(&self, bytes: &[u8]) -> Result<Vec<u8>, Error>;
-    fn decompress(&self, bytes: &[u8]) -> Result<Vec<u8>, Error>;
+pub trait Compression: Send + Sync {
+    /// compresses `bytes`, appending the result to `out`
+    fn compress_into(&self, bytes: &[u8], out: &mut Vec<u8>) -> Result<(), Error>;
+    /// decompresses `bytes`, appending the result to `out`
+    fn decompress_into(&self, bytes: &[u8], out: &mut Vec<u8>) -> Result<(), Error>;
     fn get_type(&self) -> super::Compression;
+
+    fn compress(&self, bytes: &[u8]) -> Result<Vec<u8>, Error> {
+        let mut buffer = Vec::new();
+        self.compress_into(bytes, &mut buffer)?;
+        Ok(buffer)
+    }
+
+    fn decompress(&self, bytes: &[u8]) -> Result<Vec<u8>, Error> {
+        let mut buffer = Vec::new();
+        self.decompress_into(bytes, &mut buffer)?;
+        Ok(buffer)
+    }
 }
 
 pub struct Zlib;
 
 impl Compression for Zlib {
-    fn compress(&self, bytes: &[u8]) -> Result<Vec<u8>, Error> {
-        let mut e = ZlibEncoder::new(Vec::new(), Comp::default());
+    fn compress_into(&self, bytes: &[u8], out: &mut Vec<u8>) -> Result<(), Error> {
+        let mut e = ZlibEncoder::new(out, Comp::default());
         e.write_all(bytes)?;
-        Ok(e.finish()?)
+        e.finish()?;
+        Ok(())
     }
 
-    fn decompress(&self, bytes: &[u8]) -> Result<Vec<u8>, Error> {
-        let d = ZlibDecoder::new(bytes);
-        Ok(d.bytes().collect::<Result<Vec<u8>, std::io::Error>>()?)
+    fn decompress_into(&self, bytes: &[u8], out: &mut Vec<u8>) -> Result<(), Error> {
+        ZlibDecoder::new(bytes).read_to_end(out)?;
+        Ok(())
     }
 
     fn get_type(&self) -> super::Compression {
@@ -31,15 +46,16 @@ impl Compression for Zlib {
 pub struct GZip;
 
 impl Compression for GZip {
-    fn compress(&self, bytes: &[u8]) -> Result<Vec<u8>, Error> {
-        let mut e = GzEncoder::new(Vec::new(), Comp::default());
+    fn compress_into(&self, bytes: &[u8], out: &mut Vec<u8>) -> Result<(), Error> {
+        let mut e = GzEncoder::new(out, Comp::default());
         e.write_all(bytes)?;
-        Ok(e.finish()?)
+        e.finish()?;
+        Ok(())
     }
 
-    fn decompress(&self, bytes: &[u8]) -> Result<Vec<u8>, Error> {
-        let d = GzDecoder::new(bytes);
-        Ok(d.bytes().collect::<Result<Vec<u8>, std::io::Error>>()?)
+    fn decompress_into(&self, bytes: &[u8], out: &mut Vec<u8>) -> Result<(), Error> {
+        GzDecoder::new(bytes).read_to_end(out)?;
+        Ok(())
     }
 
     fn get_type(&self) -> super::Compression {
@@ -72,3 +88,18 @@ fn gzip_test() {
 
     assert_eq!(decrypted_result.unwrap(), marker);
 }
+
+#[test]
+fn reuse_test() {
+    let marker = super::generate_eof_marker();
+    let mut compressed = Vec::new();
+    let mut decompressed = Vec::new();
+
+    for _ in 0..2 {
+        compressed.clear();
+        decompressed.clear();
+        Zlib.compress_into(&marker, &mut compressed).unwrap();
+        Zlib.decompress_into(&compressed, &mut decompressed).unwrap();
+        assert_eq!(decompressed, marker);
+    }
+}
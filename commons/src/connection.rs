@@ -1,20 +1,260 @@
-use std::{io::{Read, Write}, net::TcpStream, path};
-use crate::{DownloadMetadata, Role, UploadMetadata};
+use std::{collections::{BTreeSet, HashMap}, io::{Read, Seek, Write}, net::TcpStream, path, time::{Duration, Instant}};
+use crate::{ack::{Ack, Status, Summary}, attributes::Attributes, delta::{self, Instruction, Signature}, manifest::{self, Manifest}, mirror::{self, Mirror}, quick_check::{self, Action, Answer, Offer, QuickCheck}, quota::Account, sparse::{self, Extents, Placement}, throttle::Throttle, transport::Transport, Abort, Conflict, DownloadMetadata, EntryKind, Fsync, Preserve, Role, SymlinkPolicy, UploadMetadata};
 
-use super::{CHUNK, EOF_MARKER, checksum, compression, error, FileMetadata};
+use super::{ABORT_FRAME, CHUNK, EOF_MARKER, checksum, compression, error, FileMetadata};
 
-pub struct Link {
-    stream: TcpStream,
+/// Runs the protocol over any [`Transport`], TCP by default
+pub struct Link<T = TcpStream> {
+    stream: T,
     compression: Option<Box<dyn compression::Compression>>,
     checksum: Option<Box<dyn checksum::Checksum>>,
+    write_limit: Option<Throttle>,
+    read_limit: Option<Throttle>,
+    /// maximum bytes of a file sent per chunk
+    chunk_size: usize,
+    /// attributes sent along with each file
+    preserve: Preserve,
+    /// how links found by the sender are sent
+    symlinks: SymlinkPolicy,
+    /// refuse incoming links pointing outside of the destination
+    confine_links: bool,
+    /// (device, inode) of the multiply linked files sent so far, and the path they were sent as
+    hard_links: HashMap<(u64, u64), path::PathBuf>,
+    /// offer files to the receiver before sending them, skipping the ones it already has
+    quick_check: Option<QuickCheck>,
+    /// send only the changes of files the receiver has an older copy of
+    delta: bool,
+    /// delete what the sender doesn't have once the transfer is done
+    mirror: Option<Mirror>,
+    /// negotiate but don't send or write anything
+    dry_run: bool,
+    /// extraneous entries found by the receiver, relative to the destination
+    deletions: Vec<path::PathBuf>,
+    /// offered entries and what the receiver does with them
+    plan: Vec<(Offer, Action)>,
+    /// what the receiver does with files already at the destination
+    conflict: Conflict,
+    /// entries the receiver writes under another path, see [`Conflict::RenameNew`]
+    renames: HashMap<path::PathBuf, path::PathBuf>,
+    fsync: Fsync,
+    /// (files, directories) waiting for [`Fsync::End`]
+    unsynced: (BTreeSet<path::PathBuf>, BTreeSet<path::PathBuf>),
+    /// time spent syncing so far
+    sync_time: Duration,
+    /// share the received files count against
+    account: Option<Account>,
+    /// list every entry before sending any, with this much detail
+    manifest: Option<manifest::Detail>,
+    /// manifest received from the sender
+    listing: Option<Manifest>,
+    /// the receiver acknowledges every entry and sums the session up
+    acks: bool,
+    /// how every entry went, as seen by the receiver
+    outcomes: Vec<Ack>,
+    /// why mirroring deleted nothing, reported in the summary
+    mirror_refused: Option<String>,
+    /// why the receiver refuses the transfer, or would on a dry run
+    refusal: Option<String>,
+    buffers: Buffers,
 }
 
-impl Link {
-    pub fn new(stream: TcpStream) -> Self {
+/// Buffers reused for every chunk passing through a link.
+/// They grow to the largest chunk seen and are never shrunk, so steady state transfers don't allocate.
+#[derive(Default)]
+pub(crate) struct Buffers {
+    /// bytes as they travel on the wire, compressed if required
+    pub(crate) wire: Vec<u8>,
+    /// decompressed chunk
+    pub(crate) chunk: Vec<u8>,
+    /// checksum of the incoming chunk
+    pub(crate) checksum: Vec<u8>,
+}
+
+impl<T: Transport> Link<T> {
+    pub fn new(stream: T) -> Self {
         Self {
             stream,
             compression: None,
             checksum: None,
+            write_limit: None,
+            read_limit: None,
+            chunk_size: CHUNK,
+            preserve: Preserve::default(),
+            symlinks: SymlinkPolicy::default(),
+            confine_links: false,
+            hard_links: HashMap::new(),
+            quick_check: None,
+            delta: false,
+            mirror: None,
+            dry_run: false,
+            deletions: Vec::new(),
+            plan: Vec::new(),
+            conflict: Conflict::default(),
+            renames: HashMap::new(),
+            fsync: Fsync::default(),
+            unsynced: Default::default(),
+            sync_time: Duration::ZERO,
+            account: None,
+            manifest: None,
+            listing: None,
+            acks: false,
+            outcomes: Vec::new(),
+            mirror_refused: None,
+            refusal: None,
+            buffers: Buffers::default(),
+        }
+    }
+
+    /// caps the bytes written by [`upstream`](Self::upstream)
+    pub fn with_write_limit(self, write_limit: Option<Throttle>) -> Self {
+        Self {
+            write_limit,
+            ..self
+        }
+    }
+
+    /// caps the bytes read by [`downstream`](Self::downstream)
+    pub fn with_read_limit(self, read_limit: Option<Throttle>) -> Self {
+        Self {
+            read_limit,
+            ..self
+        }
+    }
+
+    /// sets the maximum bytes of a file sent per chunk, [`CHUNK`] by default
+    pub fn with_chunk_size(self, chunk_size: usize) -> Self {
+        Self {
+            chunk_size: chunk_size.max(1),
+            ..self
+        }
+    }
+
+    pub fn with_preserve(self, preserve: Preserve) -> Self {
+        Self {
+            preserve,
+            ..self
+        }
+    }
+
+    pub fn with_symlinks(self, symlinks: SymlinkPolicy) -> Self {
+        Self {
+            symlinks,
+            ..self
+        }
+    }
+
+    pub fn with_quick_check(self, quick_check: Option<QuickCheck>) -> Self {
+        Self {
+            quick_check,
+            ..self
+        }
+    }
+
+    pub fn with_delta(self, delta: bool) -> Self {
+        Self {
+            delta,
+            ..self
+        }
+    }
+
+    pub fn with_mirror(self, mirror: Option<Mirror>) -> Self {
+        Self {
+            mirror,
+            ..self
+        }
+    }
+
+    pub fn with_dry_run(self, dry_run: bool) -> Self {
+        Self {
+            dry_run,
+            ..self
+        }
+    }
+
+    pub fn with_conflict(self, conflict: Conflict) -> Self {
+        Self {
+            conflict,
+            ..self
+        }
+    }
+
+    pub fn with_fsync(self, fsync: Fsync) -> Self {
+        Self {
+            fsync,
+            ..self
+        }
+    }
+
+    pub fn with_manifest(self, manifest: Option<manifest::Detail>) -> Self {
+        Self {
+            manifest,
+            ..self
+        }
+    }
+
+    pub fn with_acks(self, acks: bool) -> Self {
+        Self {
+            acks,
+            ..self
+        }
+    }
+
+    /// how every entry went, as recorded by the receiver or acknowledged to the sender
+    pub fn outcomes(&self) -> &[Ack] {
+        &self.outcomes
+    }
+
+    /// manifest received from the sender, once answered
+    pub fn listing(&self) -> Option<&Manifest> {
+        self.listing.as_ref()
+    }
+
+    /// time the receiver spent syncing so far
+    pub fn sync_time(&self) -> Duration {
+        self.sync_time
+    }
+
+    /// entries the receiver found at the destination that the sender doesn't have, known once negotiated
+    pub fn deletions(&self) -> &[path::PathBuf] {
+        &self.deletions
+    }
+
+    /// offered entries and what the receiver does with each of them, known once negotiated
+    pub fn plan(&self) -> &[(Offer, Action)] {
+        &self.plan
+    }
+
+    /// whether the sender offers its entries to the receiver before sending them
+    fn negotiating(&self) -> bool {
+        self.quick_check.is_some() || self.mirror.is_some() || self.dry_run || self.conflict != Conflict::Overwrite
+    }
+
+    /// counts received files against the quota of a share, meant for the server
+    pub fn with_account(self, account: Option<Account>) -> Self {
+        Self {
+            account,
+            ..self
+        }
+    }
+
+    /// a reason found before answering, like a lack of space, the transfer would be refused for. Reported in the answer of a dry run
+    pub fn with_refusal(self, refusal: Option<String>) -> Self {
+        Self {
+            refusal,
+            ..self
+        }
+    }
+
+    /// why the receiver refused the transfer, or would have on a dry run
+    pub fn refusal(&self) -> Option<&str> {
+        self.refusal.as_deref()
+    }
+
+    /// refuses incoming links whose target escapes the destination, meant for the server
+    pub fn with_confined_links(self, confine_links: bool) -> Self {
+        Self {
+            confine_links,
+            ..self
         }
     }
 
@@ -37,37 +277,295 @@ impl Link {
             ..self
         }
     }
+
+    /// returns the underlying transport
+    pub fn into_inner(self) -> T {
+        self.stream
+    }
+
+    /// closes the underlying transport
+    pub fn shutdown(&mut self) -> Result<(), error::Error> {
+        Ok(self.stream.shutdown()?)
+    }
 }
 
 /// Methods aimed for reading from stram
-impl Link {
+impl<T: Transport> Link<T> {
+    /// receives the next entry into `destination`. The entry failing is recorded and acknowledged, only the session failing is an error.
     pub fn read_from_stream(&mut self, destination: &path::Path, marker: &[u8]) -> Result<(), error::Error> {
         tracing::info!("reading file metadata");
         let file_metadata = self.read_file_metadata()?;
-        let path = destination.join(file_metadata.rel_path);
+        let path = destination.join(self.renames.get(&file_metadata.rel_path).unwrap_or(&file_metadata.rel_path));
+
+        // links already at the destination may lead anywhere, so where the entry really lands is checked
+        let escapes = self.confine_links && confined(destination, &path, file_metadata.kind == EntryKind::Directory).is_none();
+        let status = match &file_metadata.kind {
+            EntryKind::File if escapes => self.refuse_file(&file_metadata, marker, Status::Skipped(format!("{} resolves outside of the destination", path.display())))?,
+            _ if escapes => Status::Skipped(format!("{} resolves outside of the destination", path.display())),
+            EntryKind::Directory => self.create_directory(&path, &file_metadata).unwrap_or_else(|e| Status::of(Err(e))),
+            EntryKind::Symlink { target } if self.confine_links && link_escapes(destination, &path, target) => {
+                Status::Skipped(format!("link to {} escapes the destination", target.display()))
+            },
+            EntryKind::Symlink { target } => {
+                tracing::info!("creating link {} to {}", path.display(), target.display());
+                Status::of(self.create_link(&path, |path| symlink(target, path)))
+            },
+            EntryKind::HardLink { target } => {
+                let outside = crate::escapes_tree(path::Path::new(""), target);
+                let target = destination.join(self.renames.get(target).unwrap_or(target));
+                // the last component isn't followed by `link`
+                match self.confine_links && (outside || confined(destination, &target, false).is_none()) {
+                    true => Status::Skipped(format!("hard link to {} escapes the destination", target.display())),
+                    false => {
+                        tracing::info!("creating hard link {} to {}", path.display(), target.display());
+                        Status::of(self.create_link(&path, |path| Ok(std::fs::hard_link(&target, path)?)))
+                    },
+                }
+            },
+            EntryKind::File => self.read_file(&path, &file_metadata, marker)?,
+        };
+
+        if status.is_failure() {
+            tracing::error!("{} not received, {status}", path.display());
+        }
+        else if status != Status::Ok {
+            tracing::warn!("{} {status}", path.display());
+        }
+        let ack = Ack { rel_path: file_metadata.rel_path, status };
+        if self.acks {
+            self.upstream(&bincode::serialize(&ack).unwrap())?;
+        }
+        self.outcomes.push(ack);
 
+        Ok(())
+    }
+
+    fn create_directory(&mut self, path: &path::Path, file_metadata: &FileMetadata) -> Result<Status, error::Error> {
+        tracing::info!("creating directory {}", path.display());
+        std::fs::create_dir_all(path)?;
+        let dir = std::fs::File::open(path)?;
+        let status = apply_attributes(&dir, file_metadata);
+        self.sync_parent(path)?;
+        Ok(status)
+    }
+
+    /// replaces whatever but a directory is at `path` by the link `create` makes
+    fn create_link(&mut self, path: &path::Path, create: impl FnOnce(&path::Path) -> Result<(), error::Error>) -> Result<(), error::Error> {
         if let Some(parent) = path.parent() {
             std::fs::create_dir_all(parent)?;
         }
+        if std::fs::symlink_metadata(path).is_ok_and(|m| !m.is_dir()) {
+            std::fs::remove_file(path)?;
+        }
+        create(path)?;
+        self.sync_parent(path)
+    }
+
+    /// receives the content of a regular file, a file that can't be written is read to its end all the same
+    fn read_file(&mut self, path: &path::Path, file_metadata: &FileMetadata, marker: &[u8]) -> Result<Status, error::Error> {
+        let fits = self.account.as_ref().map_or(Ok(()), |account| account.fits(file_len(path), file_metadata.size));
+        if let Err(e) = fits {
+            return self.refuse_file(file_metadata, marker, Status::QuotaExceeded(e.to_string()));
+        }
+
+        let parent = path.parent().map_or(Ok(()), std::fs::create_dir_all);
+
+        // sparse files are always sent whole
+        if self.delta && file_metadata.extents.is_none() {
+            let basis = match &parent {
+                Ok(()) if file_len(path).is_some() => std::fs::File::open(path).map_err(error::Error::from).and_then(|mut basis| Ok((Signature::read(&mut basis)?, basis)))
+                    .inspect_err(|e| tracing::warn!("unable to sign {}, receiving it whole. {e}", path.display())).ok(),
+                _ => None,
+            };
+            self.upstream(&bincode::serialize(&basis.as_ref().map(|(signature, _)| signature)).unwrap())?;
+
+            if let Some((signature, basis)) = basis {
+                tracing::info!("sent {} block signatures of {}", signature.blocks.len(), path.display());
+                return self.read_delta(path, basis, &signature, file_metadata, marker);
+            }
+        }
 
-        let mut file = std::fs::File::create(&path)?;
+        // written aside and swapped in once complete, so the final path never holds a partial file
+        let temp = temp_path(path);
+        let mut file = match parent.and_then(|_| std::fs::File::create(&temp)) {
+            Ok(file) => file,
+            Err(e) => return self.drop_file(marker, Status::of(Err(e.into()))),
+        };
+        // the disk being full only fails this file, its content is drained
+        if let Err(e) = preallocate(&file, &temp, file_metadata) {
+            return self.drop_file(marker, Status::of(Err(e)));
+        }
+        let mut placement = file_metadata.extents.as_ref().map(Extents::placement);
+        // the first error is kept, the rest of the file is only read
+        let mut failure = None;
 
         loop {
-            match self.downstream() {
-                Ok(buffer) if buffer == marker => {
-                    tracing::info!("reached end of file");
-                    break;
-                }
-                Ok(buffer) => file.write_all(&buffer)?,
-                Err(e) => {
-                    tracing::error!("error reading chunk {e}. Deleting file at {}", path.to_str().unwrap());
-                    std::fs::remove_file(&path)?;
-                    tracing::info!("file {} removed successfully", path.to_str().unwrap());
-                    break;
+            if let Err(e) = self.read_chunk() {
+                tracing::error!("error receiving {e}. Deleting file at {}", temp.display());
+                std::fs::remove_file(&temp)?;
+                return entry_failure(e);
+            }
+
+            let written = match self.unpack_chunk() {
+                Ok(buffer) if buffer == marker => break,
+                Ok(_) if failure.is_some() => continue,
+                Ok(buffer) => match placement.as_mut() {
+                    Some(placement) => write_sparse(&mut file, placement, buffer),
+                    None => file.write_all(buffer).map_err(error::Error::from),
                 },
+                Err(e) => Err(e),
+            };
+
+            if let Err(e) = written {
+                failure.get_or_insert(e);
             }
         }
 
+        if let Some(e) = failure {
+            tracing::error!("error receiving {e}. Deleting file at {}", temp.display());
+            std::fs::remove_file(&temp)?;
+            return Ok(Status::of(Err(e)));
+        }
+
+        tracing::info!("reached end of file");
+        match &file_metadata.extents {
+            // trailing holes
+            Some(extents) => file.set_len(extents.size)?,
+            // the source may have shrunk since it was announced
+            None if file_metadata.size.is_some() => {
+                let written = file.stream_position()?;
+                file.set_len(written)?;
+            },
+            None => {},
+        }
+        self.swap_in(&file, &temp, path, file_metadata)
+    }
+
+    /// rebuilds `path` in a temporary file out of `basis` and the incoming instructions, then swaps it in
+    fn read_delta(&mut self, path: &path::Path, mut basis: std::fs::File, signature: &Signature, file_metadata: &FileMetadata, marker: &[u8]) -> Result<Status, error::Error> {
+        let temp = temp_path(path);
+        let file = match std::fs::File::create(&temp) {
+            Ok(file) => file,
+            Err(e) => return self.drop_file(marker, Status::of(Err(e.into()))),
+        };
+        // the disk being full only fails this file, its content is drained
+        if let Err(e) = preallocate(&file, &temp, file_metadata) {
+            return self.drop_file(marker, Status::of(Err(e)));
+        }
+        let mut out = std::io::BufWriter::new(file);
+        let mut failure = None;
+
+        loop {
+            if let Err(e) = self.read_chunk() {
+                tracing::error!("error reading delta {e}. Deleting file at {}", temp.display());
+                std::fs::remove_file(&temp)?;
+                return entry_failure(e);
+            }
+
+            let instructions = match self.unpack_chunk() {
+                Ok(buffer) if buffer == marker => break,
+                Ok(_) if failure.is_some() => continue,
+                Ok(buffer) => bincode::deserialize::<Vec<Instruction>>(buffer).map_err(|e| error::Error::InvalidRequest(format!("invalid delta {e}"))),
+                Err(e) => Err(e),
+            };
+
+            if let Err(e) = instructions.and_then(|instructions| delta::patch(&mut basis, signature, &instructions, &mut out)) {
+                failure.get_or_insert(e);
+            }
+        }
+
+        if let Some(e) = failure {
+            tracing::error!("error reading delta {e}. Deleting file at {}", temp.display());
+            std::fs::remove_file(&temp)?;
+            return Ok(Status::of(Err(e)));
+        }
+
+        tracing::info!("reached end of file");
+        let mut file = out.into_inner().map_err(|e| e.into_error())?;
+        let written = file.stream_position()?;
+        file.set_len(written)?;
+        self.swap_in(&file, &temp, path, file_metadata)
+    }
+
+    /// reads the rest of a file that isn't written up to `marker`, so the stream stays in step, it ends with `status`
+    fn drop_file(&mut self, marker: &[u8], status: Status) -> Result<Status, error::Error> {
+        loop {
+            match self.read_chunk() {
+                // the sender gave up on it as well
+                Err(error::Error::Aborted(Abort::Entry(_))) => return Ok(status),
+                Err(e) => return Err(e),
+                Ok(()) if self.unpack_chunk().is_ok_and(|buffer| buffer == marker) => return Ok(status),
+                Ok(()) => {},
+            }
+        }
+    }
+
+    /// drops a file before anything of it is written, answering for the signature the sender waits on in delta mode
+    fn refuse_file(&mut self, file_metadata: &FileMetadata, marker: &[u8], status: Status) -> Result<Status, error::Error> {
+        tracing::error!("refusing {}. {status}", file_metadata.rel_path.display());
+        if self.delta && file_metadata.extents.is_none() {
+            self.upstream(&bincode::serialize(&None::<Signature>).unwrap())?;
+        }
+        self.drop_file(marker, status)
+    }
+
+    /// finishes a file received in `temp` and renames it to `path`, attributes are applied and the content synced first.
+    /// The file is dropped when it goes over the quota.
+    fn swap_in(&mut self, file: &std::fs::File, temp: &path::Path, path: &path::Path, file_metadata: &FileMetadata) -> Result<Status, error::Error> {
+        // the announced size may not be what was received
+        if let Some(account) = &self.account {
+            if let Err(e) = account.replace(file_len(path), Some(file.metadata()?.len())) {
+                tracing::error!("refusing {}. {e}", path.display());
+                std::fs::remove_file(temp)?;
+                return Ok(Status::QuotaExceeded(e.to_string()));
+            }
+        }
+
+        let status = apply_attributes(file, file_metadata);
+
+        match self.fsync {
+            Fsync::PerFile => self.timed_sync(file)?,
+            Fsync::End => {
+                self.unsynced.0.insert(path.to_path_buf());
+            },
+            Fsync::Never => {},
+        }
+        std::fs::rename(temp, path)?;
+        self.sync_parent(path)?;
+        Ok(status)
+    }
+
+    /// syncs the directory holding `path`, so a created or renamed entry survives a crash
+    fn sync_parent(&mut self, path: &path::Path) -> Result<(), error::Error> {
+        let Some(parent) = path.parent() else {
+            return Ok(());
+        };
+
+        match self.fsync {
+            Fsync::PerFile => self.timed_sync(&std::fs::File::open(parent)?),
+            Fsync::End => {
+                self.unsynced.1.insert(parent.to_path_buf());
+                Ok(())
+            },
+            Fsync::Never => Ok(()),
+        }
+    }
+
+    fn timed_sync(&mut self, file: &std::fs::File) -> Result<(), error::Error> {
+        let start = Instant::now();
+        file.sync_all()?;
+        self.sync_time += start.elapsed();
+        Ok(())
+    }
+
+    /// syncs everything written during the session with [`Fsync::End`], to be called once every entry is received.
+    /// Files go first, then the directories holding them.
+    pub fn sync(&mut self) -> Result<(), error::Error> {
+        let (files, dirs) = std::mem::take(&mut self.unsynced);
+        for path in files.iter().chain(&dirs) {
+            self.timed_sync(&std::fs::File::open(path)?)?;
+        }
+
         Ok(())
     }
 
@@ -78,82 +576,189 @@ impl Link {
     /// - Reads the chunk
     /// - Decrypts the buffer
     /// - Validate checksum
-    fn downstream(&mut self) -> Result<Vec<u8>, error::Error> {
-        let mut checksum = None;
-        if self.checksum.is_some() {
-            let checksum_len = self.read_len()?;
-            tracing::debug!("read checksum len: {}", checksum_len);
-            let mut checksum_bytes = vec![0; checksum_len as usize];
-            let bytes_read = self.stream.read(&mut checksum_bytes)?;
+    fn downstream(&mut self) -> Result<&[u8], error::Error> {
+        self.read_chunk()?;
+        self.unpack_chunk()
+    }
 
-            if bytes_read == 0 {
-                let err = "checksum bytes not determined";
-                tracing::error!("{}", err);
-                return Err(error::Error::integrity_error(err));
-            }
+    /// reads the next chunk as it is on the wire, only failing when the stream does or the sender aborts
+    fn read_chunk(&mut self) -> Result<(), error::Error> {
+        let mut len = self.read_len()?;
+        if len == ABORT_FRAME {
+            let abort = self.read_abort()?;
+            tracing::error!("{abort}");
+            return Err(error::Error::Aborted(abort));
+        }
 
-            checksum = String::from_utf8(checksum_bytes).ok();
+        if self.checksum.is_some() {
+            let checksum_len = len;
+            tracing::debug!("read checksum len: {}", checksum_len);
+            self.buffers.checksum.resize(checksum_len as usize, 0);
+            read_limited(&mut self.stream, self.read_limit.as_ref(), &mut self.buffers.checksum).inspect_err(|_| tracing::error!("checksum bytes not determined"))?;
+            len = self.read_len()?;
         }
 
-        let chunk = {
-            let chunk_len = self.read_len()?;
-            tracing::info!("reading bytes: {}", chunk_len);
-            let mut buffer = vec![0; chunk_len as usize];
-            self.stream.read_exact(&mut buffer)?;
+        tracing::info!("reading bytes: {}", len);
+        self.buffers.wire.resize(len as usize, 0);
+        read_limited(&mut self.stream, self.read_limit.as_ref(), &mut self.buffers.wire)
+    }
 
-            match &self.compression {
-                Some(algo) => algo.decompress(&buffer)?,
-                None => buffer,
-            }
-        };
+    /// reads the reason following [`ABORT_FRAME`], it is never compressed nor checksummed
+    fn read_abort(&mut self) -> Result<Abort, error::Error> {
+        let len = self.read_len()?;
+        self.buffers.wire.resize(len as usize, 0);
+        read_limited(&mut self.stream, self.read_limit.as_ref(), &mut self.buffers.wire)?;
+        bincode::deserialize(&self.buffers.wire).map_err(|e| error::Error::InvalidRequest(format!("invalid abort {e}")))
+    }
 
-        if let Some(algo) = &self.checksum {
-            match checksum {
-                None => {
-                    let err = "unable to parse checksum bytes";
-                    tracing::error!("{err}");
-                    Err(error::Error::integrity_error(err))
-                },
-                Some(hash) => match algo.valdate(&chunk, &hash) {
-                    true => {
-                        tracing::info!("checksum passed");
-                        Ok(chunk)
-                    },
-                    false => {
-                        let err = "checksum verification failed";
-                        tracing::error!("{err}");
-                        Err(error::Error::integrity_error(err))
-                    },
-                },
-            }
-        }
-        else {
-            Ok(chunk)
-        }
+    /// checks and decompresses the chunk last read
+    fn unpack_chunk(&mut self) -> Result<&[u8], error::Error> {
+        let checksum = match self.checksum.is_some() {
+            true => std::str::from_utf8(&self.buffers.checksum).ok(),
+            false => None,
+        };
+        unpack(self.compression.as_deref(), self.checksum.as_deref(), checksum, &self.buffers.wire, &mut self.buffers.chunk)
     }
 
     /// Method to read the first 4 bytes of a stream.
     /// Used to determine the length of the incoming message
     fn read_len(&mut self) -> Result<u32, error::Error> {
         let mut len_buf = [0; 4];
-        self.stream.read_exact(&mut len_buf)?;
+        read_limited(&mut self.stream, self.read_limit.as_ref(), &mut len_buf)?;
 
         let payload_len = u32::from_be_bytes(len_buf);
         Ok(payload_len)
     }
 
+    /// answers the offer of the sender when a quick check, mirroring, a dry run or a conflict policy is set, returns how many entries will follow.
+    /// Otherwise all `count` entries are sent. The manifest, when asked for, is read first.
+    pub fn answer(&mut self, destination: &path::Path, count: usize) -> Result<usize, error::Error> {
+        if self.manifest.is_some() {
+            let listing: Manifest = bincode::deserialize(self.downstream()?).map_err(|e| error::Error::InvalidRequest(format!("invalid manifest {e}")))?;
+            tracing::info!("manifest of {} entries, {} files for {} bytes", listing.entries.len(), listing.files(), listing.bytes());
+            self.listing = Some(listing);
+        }
+
+        if !self.negotiating() {
+            return Ok(count);
+        }
+
+        let offers: Vec<Offer> = bincode::deserialize(self.downstream()?).map_err(|e| error::Error::InvalidRequest(format!("invalid offer {e}")))?;
+        let mut actions = Vec::with_capacity(offers.len());
+        let mut taken = std::collections::HashSet::new();
+        for offer in &offers {
+            let mut action = offer.action(&destination.join(&offer.rel_path), self.quick_check, self.conflict);
+            if let Action::Rename { to } = &mut action {
+                *to = quick_check::rename(destination, &offer.rel_path, &taken);
+                taken.insert(to.clone());
+                self.renames.insert(offer.rel_path.clone(), to.clone());
+            }
+            tracing::info!("{}: {action:?}", offer.rel_path.display());
+            actions.push(action);
+        }
+
+        let failed = actions.iter().filter(|a| **a == Action::Fail).count();
+        let error = (failed > 0).then(|| format!("{failed} files already exist at {}", destination.display()));
+        let error = match (self.refusal.take(), error) {
+            (Some(refusal), Some(error)) => Some(format!("{refusal}, {error}")),
+            (refusal, error) => refusal.or(error),
+        };
+        self.refusal = error.clone();
+        let count = actions.iter().filter(|a| !matches!(a, Action::Skip | Action::Fail)).count();
+        tracing::info!("{} of {} entries already present", offers.len() - count, offers.len());
+
+        // only trees are mirrored, a single file leaves its siblings alone
+        if self.mirror.is_some() && offers.iter().any(|offer| offer.rel_path.as_os_str().is_empty()) {
+            self.deletions = mirror::extraneous(destination, offers.iter().map(|offer| &offer.rel_path));
+        }
+
+        self.upstream(&bincode::serialize(&Answer { actions: actions.clone(), deletions: self.deletions.clone(), error: error.clone() }).unwrap())?;
+        self.plan = offers.into_iter().zip(actions).collect();
+
+        match error {
+            Some(e) if !self.dry_run => {
+                tracing::error!("refusing transfer. {e}");
+                Err(error::Error::InvalidRequest(e))
+            },
+            _ => Ok(if self.dry_run { 0 } else { count }),
+        }
+    }
+
+    /// deletes the extraneous entries found while answering, to be called once every entry is received.
+    /// Nothing is deleted on a dry run, when an entry failed or past the maximum of deletions, the refusal is kept for the summary.
+    pub fn mirror(&mut self, destination: &path::Path) -> Result<(), error::Error> {
+        let Some(mirror) = self.mirror.filter(|_| !self.dry_run) else {
+            return Ok(());
+        };
+
+        let mirrored = self.delete_extraneous(mirror, destination);
+        if let Err(e) = &mirrored {
+            self.mirror_refused = Some(e.to_string());
+        }
+        mirrored
+    }
+
+    fn delete_extraneous(&mut self, mirror: Mirror, destination: &path::Path) -> Result<(), error::Error> {
+        // what failed may well be what the destination would otherwise be left with
+        let failed = self.outcomes.iter().filter(|ack| ack.status.is_failure()).count();
+        if failed > 0 {
+            return Err(error::Error::InvalidRequest(format!("refusing to delete anything, {failed} entries failed")));
+        }
+
+        mirror.check(self.deletions.len())?;
+        let freed = self.deletions.iter().filter_map(|rel| file_len(&destination.join(rel))).collect::<Vec<u64>>();
+        mirror::delete(destination, &self.deletions)?;
+
+        if let Some(account) = &self.account {
+            for len in freed {
+                account.replace(Some(len), None)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// writes the manifest received from the sender at the root of `destination` and compares the destination to it,
+    /// to be called once every entry is received. `None` without a manifest or on a dry run.
+    pub fn check_manifest(&mut self, destination: &path::Path) -> Result<Option<manifest::Report>, error::Error> {
+        let Some(listing) = self.listing.as_ref().filter(|_| !self.dry_run) else {
+            return Ok(None);
+        };
+
+        listing.write(&destination.join(manifest::MANIFEST))?;
+        Ok(Some(listing.compare(destination, &self.renames)))
+    }
+
+    /// sums up the entries received so far and sends it to the sender when acknowledging, to be called once the session is done
+    pub fn write_summary(&mut self) -> Result<Summary, error::Error> {
+        let summary = Summary::new(&self.outcomes, self.sync_time).with_mirror_refused(self.mirror_refused.clone());
+        if self.acks {
+            self.upstream(&bincode::serialize(&summary).unwrap())?;
+        }
+        Ok(summary)
+    }
+
     /// reader for [`DownloadMetadata`]
     pub fn read_download_metadata(&mut self) -> Result<DownloadMetadata, error::Error> {
         let len = self.read_len()?;
         let mut buffer = vec![0; len as usize];
 
-        self.stream.read_exact(&mut buffer)?;
+        read_limited(&mut self.stream, self.read_limit.as_ref(), &mut buffer)?;
 
         let download_metadata = DownloadMetadata::from_bytes(&buffer);
 
         tracing::debug!("received download metadata: {:?}", download_metadata);
         self.compression = download_metadata.compression.as_ref().map(|c| c.get_algo());
         self.checksum = download_metadata.checksum.as_ref().map(|c| c.get_algo());
+        self.preserve = download_metadata.preserve;
+        self.symlinks = download_metadata.symlinks;
+        self.quick_check = download_metadata.quick_check;
+        self.delta = download_metadata.delta;
+        self.mirror = download_metadata.mirror;
+        self.dry_run = download_metadata.dry_run;
+        self.conflict = download_metadata.conflict;
+        self.fsync = download_metadata.fsync;
+        self.manifest = download_metadata.manifest;
+        self.acks = download_metadata.acks;
 
         Ok(download_metadata)
     }
@@ -163,18 +768,18 @@ impl Link {
         let len = self.read_len()?;
         let mut buffer = vec![0; len as usize];
 
-        self.stream.read_exact(&mut buffer)?;
+        read_limited(&mut self.stream, self.read_limit.as_ref(), &mut buffer)?;
         Ok(Role::from_bytes(&buffer))
     }
 
     pub fn read_result(&mut self) -> Result<super::Result, error::Error> {
         let bytes = self.downstream()?;
-        Ok(super::Result::from_bytes(&bytes))
+        Ok(super::Result::from_bytes(bytes))
     }
 
     fn read_file_metadata(&mut self) -> Result<FileMetadata, error::Error> {
         let bytes = self.downstream()?;
-        Ok(super::FileMetadata::from_bytes(&bytes))
+        Ok(super::FileMetadata::from_bytes(bytes))
     }
 
     /// raw read
@@ -182,42 +787,235 @@ impl Link {
         let len = self.read_len()?;
         let mut buffer = vec![0; len as usize];
 
-        self.stream.read_exact(&mut buffer)?;
+        read_limited(&mut self.stream, self.read_limit.as_ref(), &mut buffer)?;
         let upload_metadata = UploadMetadata::from_bytes(&buffer);
 
         self.compression = upload_metadata.compression.as_ref().map(|c| c.get_algo());
         self.checksum = upload_metadata.checksum.as_ref().map(|c| c.get_algo());
+        self.preserve = upload_metadata.preserve;
+        self.quick_check = upload_metadata.quick_check;
+        self.delta = upload_metadata.delta;
+        self.mirror = upload_metadata.mirror;
+        self.dry_run = upload_metadata.dry_run;
+        self.conflict = upload_metadata.conflict;
+        self.fsync = upload_metadata.fsync;
+        self.manifest = upload_metadata.manifest;
+        self.acks = upload_metadata.acks;
 
         Ok(upload_metadata)
     }
 }
 
 /// Methods aimed for writing to stram
-impl Link {
+impl<T: Transport> Link<T> {
+    /// sends the entry at `source` as `relative_path` and waits for its acknowledgement when asked for
     pub fn write_to_stream(&mut self, source: &path::Path, relative_path: &path::Path) -> Result<(), error::Error> {
-        self.write_file_metadata(relative_path)?;
+        self.send_entry(source, relative_path)?;
+        if !self.acks {
+            return Ok(());
+        }
+
+        let ack: Ack = bincode::deserialize(self.downstream()?).map_err(|e| error::Error::InvalidRequest(format!("invalid acknowledgement {e}")))?;
+        if ack.status.is_failure() {
+            tracing::error!("{} not received, {}", relative_path.display(), ack.status);
+        }
+        else if ack.status != Status::Ok {
+            tracing::warn!("{} {}", relative_path.display(), ack.status);
+        }
+        self.outcomes.push(ack);
+        Ok(())
+    }
+
+    /// reads the summary of the receiver once every entry is sent, `None` without acknowledgements
+    pub fn read_summary(&mut self) -> Result<Option<Summary>, error::Error> {
+        if !self.acks {
+            return Ok(None);
+        }
+
+        let summary = bincode::deserialize(self.downstream()?).map_err(|e| error::Error::InvalidRequest(format!("invalid summary {e}")))?;
+        Ok(Some(summary))
+    }
+
+    /// an entry that can't be read is announced all the same and aborted, so the receiver keeps count
+    fn send_entry(&mut self, source: &path::Path, relative_path: &path::Path) -> Result<(), error::Error> {
+        let (file_metadata, file) = match self.describe(source, relative_path) {
+            Ok(described) => described,
+            Err(e) => {
+                self.write_file_metadata(&FileMetadata::new(relative_path))?;
+                if self.delta {
+                    // the signature of the copy the receiver may have
+                    self.downstream()?;
+                }
+                return self.abort_entry(relative_path, e);
+            },
+        };
+        self.write_file_metadata(&file_metadata)?;
+
+        match file {
+            Some(file) => self.send_content(file, &file_metadata),
+            None => Ok(()),
+        }
+    }
+
+    /// metadata of the entry at `source`, along with the opened file for regular files.
+    /// Only reads from the filesystem, nothing is sent.
+    fn describe(&mut self, source: &path::Path, relative_path: &path::Path) -> Result<(FileMetadata, Option<std::fs::File>), error::Error> {
+        let mut metadata = std::fs::symlink_metadata(source)?;
+        // what a followed link points to, its attributes are the ones sent
+        let mut resolved = source.to_path_buf();
+        if metadata.is_symlink() {
+            if self.symlinks == SymlinkPolicy::CopyLink {
+                let target = std::fs::read_link(source)?;
+                tracing::info!("sending link {} to {}", relative_path.display(), target.display());
+                return Ok((FileMetadata::new(relative_path).with_kind(EntryKind::Symlink { target }), None));
+            }
+            metadata = std::fs::metadata(source)?;
+            resolved = std::fs::canonicalize(source)?;
+        }
+        let attributes = Attributes::read(&metadata, &self.preserve);
+        let xattrs = match self.preserve.xattrs {
+            true => crate::xattrs::read(&resolved).inspect_err(|e| tracing::warn!("unable to read extended attributes of {}. {e}", source.display())).ok(),
+            false => None,
+        };
+
+        if metadata.is_dir() {
+            tracing::info!("sending directory {}", relative_path.display());
+            return Ok((FileMetadata::new(relative_path).with_kind(EntryKind::Directory).with_attributes(attributes).with_xattrs(xattrs), None));
+        }
+
+        #[cfg(unix)]
+        if std::os::unix::fs::MetadataExt::nlink(&metadata) > 1 {
+            match self.hard_links.entry(crate::file_id(&metadata)) {
+                std::collections::hash_map::Entry::Occupied(entry) => {
+                    let target = entry.get().clone();
+                    tracing::info!("sending {} as a hard link to {}", relative_path.display(), target.display());
+                    return Ok((FileMetadata::new(relative_path).with_kind(EntryKind::HardLink { target }), None));
+                },
+                std::collections::hash_map::Entry::Vacant(entry) => {
+                    entry.insert(relative_path.to_path_buf());
+                },
+            }
+        }
 
         let file = std::fs::File::open(source)?;
-        let mut reader = std::io::BufReader::new(file);
-        let mut buffer = [0; CHUNK];
+        let file_metadata = FileMetadata::new(relative_path).with_attributes(attributes).with_xattrs(xattrs).with_extents(Extents::read(&file, &metadata)).with_size(Some(metadata.len()));
+        Ok((file_metadata, Some(file)))
+    }
 
-        loop {
-            let bytes_read = reader.read(&mut buffer)?;
-            if bytes_read == 0 {
-                tracing::info!("reached end of file");
-                self.upstream(EOF_MARKER.as_ref())?;
-                break;
+    /// sends the content of a regular file up to the end of file marker, the file failing to read aborts it
+    fn send_content(&mut self, mut file: std::fs::File, file_metadata: &FileMetadata) -> Result<(), error::Error> {
+        let relative_path = file_metadata.rel_path.as_path();
+
+        if self.delta && file_metadata.extents.is_none() {
+            let signature: Option<Signature> = bincode::deserialize(self.downstream()?).map_err(|e| error::Error::InvalidRequest(format!("invalid signature {e}")))?;
+            if let Some(signature) = signature {
+                tracing::info!("sending delta of {} against {} blocks", relative_path.display(), signature.blocks.len());
+                let chunk_size = self.chunk_size;
+                // told apart from the file failing to read
+                let mut stream_failed = false;
+                let sent = delta::Diff::new(&signature).run(std::io::BufReader::new(file), chunk_size, |instructions| {
+                    self.upstream(&bincode::serialize(instructions).unwrap()).inspect_err(|_| stream_failed = true)
+                });
+                return match sent {
+                    Ok(()) => self.upstream(EOF_MARKER.as_ref()),
+                    Err(e) if !stream_failed => self.abort_entry(relative_path, e),
+                    Err(e) => Err(e),
+                };
+            }
+        }
+
+        // only the data regions of sparse files are read, holes are left to the receiver
+        let regions = file_metadata.extents.as_ref().map_or(vec![(0, u64::MAX)], |e| e.data.clone());
+        let mut buffer = vec![0; self.chunk_size];
+
+        for (offset, len) in regions {
+            if let Err(e) = file.seek(std::io::SeekFrom::Start(offset)) {
+                return self.abort_entry(relative_path, e.into());
             }
+            let mut reader = std::io::BufReader::new(&file).take(len);
 
-            self.upstream(&buffer)?;
+            loop {
+                let bytes_read = match reader.read(&mut buffer) {
+                    Ok(bytes_read) => bytes_read,
+                    Err(e) => return self.abort_entry(relative_path, e.into()),
+                };
+                if bytes_read == 0 {
+                    break;
+                }
+
+                self.upstream(&buffer[..bytes_read])?;
+            }
         }
 
-        Ok(())
+        tracing::info!("reached end of file");
+        self.upstream(EOF_MARKER.as_ref())
+    }
+
+    /// sends an [`Abort::Entry`] in place of the rest of the entry, the receiver drops it and goes on
+    fn abort_entry(&mut self, relative_path: &path::Path, e: error::Error) -> Result<(), error::Error> {
+        tracing::error!("aborting {}. {e}", relative_path.display());
+        self.write_abort(&Abort::Entry(e.to_string()))
+    }
+
+    /// sends an [`Abort::Session`] with the reason of `e` as best it can, `e` is handed back
+    fn abort_session(&mut self, e: error::Error) -> error::Error {
+        tracing::error!("aborting the session. {e}");
+        if let Err(e) = self.write_abort(&Abort::Session(e.to_string())) {
+            tracing::error!("unable to tell the peer. {e}");
+        }
+        e
+    }
+
+    /// writes [`ABORT_FRAME`] then the length prefixed abort, neither compressed nor checksummed
+    fn write_abort(&mut self, abort: &Abort) -> Result<(), error::Error> {
+        let bytes = bincode::serialize(abort).unwrap();
+        write_len(&mut self.stream, ABORT_FRAME as usize)?;
+        write_len(&mut self.stream, bytes.len())?;
+        write_limited(&mut self.stream, self.write_limit.as_ref(), &bytes)
+    }
+
+    /// offers `entries`, as (source, relative path), to the receiver when a quick check, mirroring, a dry run or a conflict policy is set and keeps the ones it asks for.
+    /// Otherwise every entry is kept, on a dry run none are. The manifest of every entry, when asked for, is sent first.
+    pub fn negotiate(&mut self, entries: Vec<(path::PathBuf, path::PathBuf)>) -> Result<Vec<(path::PathBuf, path::PathBuf)>, error::Error> {
+        // the receiver waits on what can't be read, it is told why instead
+        if let Some(detail) = self.manifest {
+            let manifest = Manifest::read(&entries, self.symlinks, detail).map_err(|e| self.abort_session(e))?;
+            self.upstream(&bincode::serialize(&manifest).unwrap())?;
+        }
+
+        if !self.negotiating() {
+            return Ok(entries);
+        }
+
+        let (symlinks, quick_check) = (self.symlinks, self.quick_check);
+        let offers = entries.iter().map(|(source, rel)| {
+            let metadata = match symlinks {
+                SymlinkPolicy::CopyLink => std::fs::symlink_metadata(source)?,
+                _ => std::fs::metadata(source)?,
+            };
+            match metadata.is_file() {
+                true => Offer::file(source, rel, &metadata, quick_check),
+                false => Ok(Offer::other(rel)),
+            }
+        }).collect::<Result<Vec<Offer>, error::Error>>().map_err(|e| self.abort_session(e))?;
+        self.upstream(&bincode::serialize(&offers).unwrap())?;
+
+        let answer: Answer = bincode::deserialize(self.downstream()?).map_err(|e| error::Error::InvalidRequest(format!("invalid answer {e}")))?;
+        self.deletions = answer.deletions;
+        self.plan = offers.into_iter().zip(answer.actions.iter().cloned()).collect();
+        self.refusal = answer.error.clone();
+        if self.dry_run {
+            return Ok(Vec::new());
+        }
+        if let Some(e) = answer.error {
+            return Err(error::Error::InvalidRequest(e));
+        }
+
+        Ok(entries.into_iter().zip(answer.actions).filter_map(|(entry, action)| (!matches!(action, Action::Skip | Action::Fail)).then_some(entry)).collect())
     }
 
     /// method to send file metadata
-    pub fn write_file_metadata(&mut self, relative_path: &path::Path) -> Result<(), error::Error> {
-        let file_metadata = FileMetadata::new(relative_path);
+    pub fn write_file_metadata(&mut self, file_metadata: &FileMetadata) -> Result<(), error::Error> {
         self.upstream(&file_metadata.to_bytes())
     }
 
@@ -229,37 +1027,30 @@ impl Link {
     /// - Sends the length of the compressed chunk
     /// - Sends the compressed chunk
     fn upstream(&mut self, buffer: &[u8]) -> Result<(), error::Error> {
-        if let Some(ref algo) = self.checksum {
-            let checksum = algo.generate(buffer);
+        let (checksum, buffer) = pack(self.compression.as_deref(), self.checksum.as_deref(), buffer, &mut self.buffers.wire)?;
+
+        if let Some(checksum) = checksum {
             let checksum_bytes = checksum.as_bytes();
             tracing::debug!("writing checksum");
-            self.write_len(checksum_bytes.len())?;
-            self.stream.write_all(checksum_bytes)?;
+            write_len(&mut self.stream, checksum_bytes.len())?;
+            write_limited(&mut self.stream, self.write_limit.as_ref(), checksum_bytes)?;
         }
 
-        let buffer = match self.compression {
-            None => buffer.to_vec(),
-            Some(ref algo) => algo.compress(buffer)?,
-        };
-
         tracing::info!("writing buffer");
-        self.write_len(buffer.len())?;
-        self.stream.write_all(&buffer)?;
+        write_len(&mut self.stream, buffer.len())?;
+        write_limited(&mut self.stream, self.write_limit.as_ref(), buffer)?;
 
         Ok(())
     }
 
     /// method to write length to the stream
     fn write_len(&mut self, len: usize) -> Result<(), error::Error> {
-        let len = len as u32;
-        self.stream.write_all(len.to_be_bytes().as_ref())?;
-        tracing::info!("written len: {}", len);
-        Ok(())
+        write_len(&mut self.stream, len)
     }
 
     /// method to write the download metadata to the stream
     pub fn write_download_metadata(&mut self, destination: &path::Path) -> Result<(), error::Error> {
-        let download_metadata = DownloadMetadata::new(destination).with_compression(self.compression.as_ref().map(|c| c.get_type())).with_checksum(self.checksum.as_ref().map(|c| c.get_type()));
+        let download_metadata = DownloadMetadata::new(destination).with_compression(self.compression.as_ref().map(|c| c.get_type())).with_checksum(self.checksum.as_ref().map(|c| c.get_type())).with_preserve(self.preserve).with_symlinks(self.symlinks).with_quick_check(self.quick_check).with_delta(self.delta).with_mirror(self.mirror).with_dry_run(self.dry_run).with_conflict(self.conflict).with_fsync(self.fsync).with_manifest(self.manifest).with_acks(self.acks);
         let bytes = download_metadata.to_bytes();
         self.write_len(bytes.len())?;
         self.stream.write_all(&bytes)?;
@@ -290,8 +1081,8 @@ impl Link {
     }
 
     /// raw upload
-    pub fn write_upload_metadata(&mut self, count: usize, destination: &path::Path) -> Result<(), error::Error> {
-        let upload_metadata = UploadMetadata::new(count as u32, destination).with_compression(self.compression.as_ref().map(|c| c.get_type())).with_checksum(self.checksum.as_ref().map(|c| c.get_type()));
+    pub fn write_upload_metadata(&mut self, count: usize, bytes: u64, destination: &path::Path) -> Result<(), error::Error> {
+        let upload_metadata = UploadMetadata::new(count as u32, destination).with_bytes(bytes).with_compression(self.compression.as_ref().map(|c| c.get_type())).with_checksum(self.checksum.as_ref().map(|c| c.get_type())).with_preserve(self.preserve).with_quick_check(self.quick_check).with_delta(self.delta).with_mirror(self.mirror).with_dry_run(self.dry_run).with_conflict(self.conflict).with_fsync(self.fsync).with_manifest(self.manifest).with_acks(self.acks);
         let bytes = upload_metadata.to_bytes();
         self.write_len(bytes.len())?;
         self.stream.write_all(&bytes)?;
@@ -299,3 +1090,603 @@ impl Link {
 
     }
 }
+
+/// creates a symbolic link at `path` pointing to `target`
+#[cfg(unix)]
+fn symlink(target: &path::Path, path: &path::Path) -> Result<(), error::Error> {
+    Ok(std::os::unix::fs::symlink(target, path)?)
+}
+
+#[cfg(not(unix))]
+fn symlink(_target: &path::Path, path: &path::Path) -> Result<(), error::Error> {
+    tracing::warn!("links not supported on this platform, skipping {}", path.display());
+    Ok(())
+}
+
+/// sibling of `path` a file is written to before being swapped in
+pub(crate) fn temp_path(path: &path::Path) -> path::PathBuf {
+    let mut name = std::ffi::OsString::from(".");
+    name.push(path.file_name().unwrap_or_default());
+    name.push(".atilink-tmp");
+    path.with_file_name(name)
+}
+
+/// where `path` lands relative to `destination` once the links on its way are resolved, `None` when outside of it.
+/// The last component is only followed when `follow` is set.
+pub(crate) fn confined(destination: &path::Path, path: &path::Path, follow: bool) -> Option<path::PathBuf> {
+    let root = crate::resolve(destination);
+    let resolved = match (follow, path.parent(), path.file_name()) {
+        _ if path == destination => root.clone(),
+        (false, Some(parent), Some(name)) => crate::resolve(parent).join(name),
+        _ => crate::resolve(path),
+    };
+    resolved.strip_prefix(&root).ok().map(path::Path::to_path_buf)
+}
+
+/// whether a link created at `path` pointing to `target` leads outside of `destination`, through the links already there as well
+pub(crate) fn link_escapes(destination: &path::Path, path: &path::Path, target: &path::Path) -> bool {
+    let Some(rel_path) = confined(destination, path, false) else {
+        return true;
+    };
+    crate::escapes_tree(&rel_path, target) || path.parent().is_some_and(|parent| confined(destination, &parent.join(target), true).is_none())
+}
+
+/// the sender dropping the entry only fails the entry, anything else going wrong with the stream fails the session
+pub(crate) fn entry_failure(e: error::Error) -> Result<Status, error::Error> {
+    match e {
+        error::Error::Aborted(Abort::Entry(_)) => Ok(Status::of(Err(e))),
+        e => Err(e),
+    }
+}
+
+/// length of the regular file at `path`, `None` when there is none
+fn file_len(path: &path::Path) -> Option<u64> {
+    std::fs::symlink_metadata(path).ok().filter(std::fs::Metadata::is_file).map(|m| m.len())
+}
+
+/// reserves the announced size of a file in `temp` before its content arrives, holes of sparse files are left out.
+/// Fails when the disk can't hold it, `temp` is removed then.
+fn preallocate(file: &std::fs::File, temp: &path::Path, file_metadata: &FileMetadata) -> Result<(), error::Error> {
+    let Some(size) = file_metadata.size else {
+        return Ok(());
+    };
+
+    let regions = file_metadata.extents.as_ref().map_or(vec![(0, size)], |e| e.data.clone());
+    for (offset, len) in regions {
+        if let Err(e) = sparse::allocate(file, offset, len) {
+            tracing::error!("unable to reserve {size} bytes for {}. {e}", temp.display());
+            std::fs::remove_file(temp)?;
+            return Err(e.into());
+        }
+    }
+
+    Ok(())
+}
+
+/// sets the extended attributes and attributes of a received entry, best effort, reporting the ones that could not be set
+fn apply_attributes(file: &std::fs::File, file_metadata: &FileMetadata) -> Status {
+    let mut unset = file_metadata.xattrs.as_ref().map_or(Vec::new(), |xattrs| {
+        crate::xattrs::apply(file, xattrs).into_iter().map(|(name, e)| format!("{name} {e}")).collect()
+    });
+    if let Err(e) = file_metadata.attributes.apply(file) {
+        unset.push(format!("attributes {e}"));
+    }
+
+    match unset.is_empty() {
+        true => Status::Ok,
+        false => Status::AttributesNotSet(unset.join(", ")),
+    }
+}
+
+/// writes a chunk of a sparse file to the extents it belongs to
+fn write_sparse(file: &mut std::fs::File, placement: &mut Placement, mut buffer: &[u8]) -> Result<(), error::Error> {
+    while !buffer.is_empty() {
+        let Some((offset, n)) = placement.next(buffer.len()) else {
+            return Err(error::Error::integrity_error("received more data than the extents of the file"));
+        };
+
+        file.seek(std::io::SeekFrom::Start(offset))?;
+        file.write_all(&buffer[..n])?;
+        buffer = &buffer[n..];
+    }
+
+    Ok(())
+}
+
+/// method to write length to the stream
+fn write_len(stream: &mut impl Write, len: usize) -> Result<(), error::Error> {
+    let len = len as u32;
+    stream.write_all(len.to_be_bytes().as_ref())?;
+    tracing::info!("written len: {}", len);
+    Ok(())
+}
+
+/// writes the buffer honouring the write limit
+fn write_limited(stream: &mut impl Write, limit: Option<&Throttle>, buffer: &[u8]) -> Result<(), error::Error> {
+    match limit {
+        None => stream.write_all(buffer)?,
+        Some(throttle) => for slice in buffer.chunks(throttle.burst()) {
+            throttle.acquire(slice.len());
+            stream.write_all(slice)?;
+        },
+    }
+    Ok(())
+}
+
+/// fills the buffer honouring the read limit
+fn read_limited(stream: &mut impl Read, limit: Option<&Throttle>, buffer: &mut [u8]) -> Result<(), error::Error> {
+    match limit {
+        None => stream.read_exact(buffer)?,
+        Some(throttle) => for slice in buffer.chunks_mut(throttle.burst()) {
+            throttle.acquire(slice.len());
+            stream.read_exact(slice)?;
+        },
+    }
+    Ok(())
+}
+
+/// Prepares a chunk for the wire.
+/// Returns the checksum of the raw bytes (if any) and the payload.
+/// The payload is the chunk itself, or the compressed chunk written into `wire`.
+/// Shared by every link implementation so that the wire format stays the same.
+pub(crate) fn pack<'a>(compression: Option<&dyn compression::Compression>, checksum: Option<&dyn checksum::Checksum>, buffer: &'a [u8], wire: &'a mut Vec<u8>) -> Result<(Option<String>, &'a [u8]), error::Error> {
+    let hash = checksum.map(|algo| algo.generate(buffer));
+
+    let buffer = match compression {
+        None => buffer,
+        Some(algo) => {
+            wire.clear();
+            algo.compress_into(buffer, wire)?;
+            wire
+        },
+    };
+
+    Ok((hash, buffer))
+}
+
+/// Reverses [`pack`].
+/// Decompresses the payload into `chunk` if required and validates it against the received checksum.
+pub(crate) fn unpack<'a>(compression: Option<&dyn compression::Compression>, checksum: Option<&dyn checksum::Checksum>, hash: Option<&str>, wire: &'a [u8], chunk: &'a mut Vec<u8>) -> Result<&'a [u8], error::Error> {
+    let chunk = match compression {
+        Some(algo) => {
+            chunk.clear();
+            algo.decompress_into(wire, chunk)?;
+            chunk
+        },
+        None => wire,
+    };
+
+    if let Some(algo) = checksum {
+        match hash {
+            None => {
+                let err = "unable to parse checksum bytes";
+                tracing::error!("{err}");
+                Err(error::Error::integrity_error(err))
+            },
+            Some(hash) => match algo.valdate(chunk, hash) {
+                true => {
+                    tracing::info!("checksum passed");
+                    Ok(chunk)
+                },
+                false => {
+                    let err = "checksum verification failed";
+                    tracing::error!("{err}");
+                    Err(error::Error::integrity_error(err))
+                },
+            },
+        }
+    }
+    else {
+        Ok(chunk)
+    }
+}
+
+#[test]
+fn chunk_round_trip() {
+    let compressions: [Option<Box<dyn compression::Compression>>; 3] = [None, Some(Box::new(compression::Zlib)), Some(Box::new(compression::GZip))];
+    for compression in compressions {
+        let compression_type = compression.as_ref().map(|c| c.get_type());
+        let (left, right) = crate::transport::pipe();
+        let mut sender = Link::new(left).with_compression(compression).with_checksum(Some(Box::new(checksum::Sha256)));
+        let mut receiver = Link::new(right).with_compression(compression_type.map(|c| c.get_algo())).with_checksum(Some(Box::new(checksum::Sha256)));
+
+        sender.upstream(b"some chunk").unwrap();
+        assert_eq!(receiver.downstream().unwrap(), b"some chunk");
+    }
+}
+
+#[test]
+fn checksum_mismatch() {
+    let (left, right) = crate::transport::pipe();
+    let mut sender = Link::new(left).with_checksum(Some(Box::new(checksum::Md5)));
+    let mut receiver = Link::new(right).with_checksum(Some(Box::new(checksum::Sha256)));
+
+    sender.upstream(b"some chunk").unwrap();
+    assert!(matches!(receiver.downstream(), Err(error::Error::IntegrityError(_))));
+}
+
+#[test]
+fn file_round_trip() {
+    let dir = tempfile::tempdir().unwrap();
+    let source = dir.path().join("source.bin");
+    let sink = dir.path().join("sink");
+    let content = (0..CHUNK + 3).map(|i| (i % 7) as u8).collect::<Vec<u8>>();
+    std::fs::write(&source, &content).unwrap();
+
+    let (left, right) = crate::transport::pipe();
+    let sender = std::thread::spawn({
+        let (source, sink) = (source.clone(), sink.clone());
+        move || {
+            let mut link = Link::new(left).with_compression(Some(Box::new(compression::Zlib)));
+            link.write_upload_metadata(1, CHUNK as u64 + 3, &sink).unwrap();
+            link.write_to_stream(&source, path::Path::new("nested/file.bin")).unwrap();
+        }
+    });
+
+    let mut link = Link::new(right);
+    let metadata = link.read_upload_metadata().unwrap();
+    link.read_from_stream(&metadata.destination, &metadata.eof_marker).unwrap();
+    sender.join().unwrap();
+
+    assert_eq!(std::fs::read(sink.join("nested/file.bin")).unwrap(), content);
+}
+
+/// runs `send` on one end of a pipe in its own thread, as the client would, while the other end, set up by `receiver`, answers and receives every entry announced, as the server would.
+/// Returns what `send` returned along with the receiving link and how many entries it received
+#[cfg(test)]
+fn transfer<T: Send + 'static>(send: impl FnOnce(Link<crate::transport::Pipe>) -> T + Send + 'static, receiver: impl FnOnce(Link<crate::transport::Pipe>) -> Link<crate::transport::Pipe>) -> (T, Link<crate::transport::Pipe>, Result<usize, error::Error>) {
+    let (left, right) = crate::transport::pipe();
+    let sender = std::thread::spawn(move || send(Link::new(left)));
+
+    let mut link = receiver(Link::new(right));
+    let received = link.read_upload_metadata().and_then(|metadata| {
+        let count = link.answer(&metadata.destination, metadata.count as usize)?;
+        for _ in 0..count {
+            link.read_from_stream(&metadata.destination, &metadata.eof_marker)?;
+        }
+        Ok(count)
+    });
+    (sender.join().unwrap(), link, received)
+}
+
+#[test]
+fn delta_round_trip() {
+    let dir = tempfile::tempdir().unwrap();
+    let source = dir.path().join("source");
+    let sink = dir.path().join("sink");
+    let old = (0..200_000u32).map(|i| (i.wrapping_mul(2654435761) >> 13) as u8).collect::<Vec<u8>>();
+    let mut new = old.clone();
+    new[100_000..100_100].fill(0);
+    std::fs::create_dir_all(&source).unwrap();
+    std::fs::create_dir_all(&sink).unwrap();
+    std::fs::write(source.join("changed"), &new).unwrap();
+    std::fs::write(sink.join("changed"), &old).unwrap();
+    std::fs::write(source.join("new"), b"new").unwrap();
+    std::fs::write(sink.join("vanished"), b"kept").unwrap();
+
+    let (statuses, _, received) = transfer({
+        let (source, sink) = (source.clone(), sink.clone());
+        move |link| {
+            let mut link = link.with_delta(true).with_acks(true);
+            link.write_upload_metadata(3, 0, &sink).unwrap();
+            for name in ["changed", "new", "vanished"] {
+                link.write_to_stream(&source.join(name), path::Path::new(name)).unwrap();
+            }
+            link.outcomes().iter().map(|ack| ack.status.clone()).collect::<Vec<Status>>()
+        }
+    }, |link| link);
+
+    assert_eq!(received.unwrap(), 3);
+    assert_eq!(statuses[..2], [Status::Ok, Status::Ok]);
+    assert!(matches!(statuses[2], Status::IoError(_)));
+    assert_eq!(std::fs::read(sink.join("changed")).unwrap(), new);
+    assert_eq!(std::fs::read(sink.join("new")).unwrap(), b"new");
+    // the copy it would have been rebuilt from is left untouched
+    assert_eq!(std::fs::read(sink.join("vanished")).unwrap(), b"kept");
+    assert!(!temp_path(&sink.join("vanished")).exists());
+}
+
+#[test]
+fn interrupted_file() {
+    let dir = tempfile::tempdir().unwrap();
+    std::fs::write(dir.path().join("file"), b"old").unwrap();
+
+    let (left, right) = crate::transport::pipe();
+    let mut sender = Link::new(left);
+    sender.write_upload_metadata(1, 0, dir.path()).unwrap();
+    sender.write_file_metadata(&FileMetadata::new(path::Path::new("file"))).unwrap();
+    sender.upstream(b"partial").unwrap();
+    drop(sender);
+
+    let mut link = Link::new(right);
+    let metadata = link.read_upload_metadata().unwrap();
+    // the stream is gone, so is the session
+    assert!(link.read_from_stream(&metadata.destination, &metadata.eof_marker).is_err());
+
+    assert_eq!(std::fs::read(dir.path().join("file")).unwrap(), b"old");
+    assert!(!temp_path(&dir.path().join("file")).exists());
+}
+
+#[test]
+fn failed_file_ack() {
+    let dir = tempfile::tempdir().unwrap();
+    let source = dir.path().join("source");
+    let sink = dir.path().join("sink");
+    std::fs::create_dir_all(&sink).unwrap();
+    std::fs::write(sink.join("blocked"), b"not a directory").unwrap();
+    std::fs::write(&source, b"content").unwrap();
+
+    let (left, right) = crate::transport::pipe();
+    let sender = std::thread::spawn({
+        let (source, sink) = (source.clone(), sink.clone());
+        move || {
+            let mut link = Link::new(left).with_acks(true);
+            link.write_upload_metadata(2, 14, &sink).unwrap();
+            link.write_to_stream(&source, path::Path::new("blocked/file")).unwrap();
+            link.write_to_stream(&source, path::Path::new("file")).unwrap();
+            link.read_summary().unwrap().unwrap()
+        }
+    });
+
+    let mut link = Link::new(right);
+    let metadata = link.read_upload_metadata().unwrap();
+    for _ in 0..metadata.count {
+        link.read_from_stream(&metadata.destination, &metadata.eof_marker).unwrap();
+    }
+    link.write_summary().unwrap();
+    let summary = sender.join().unwrap();
+
+    assert_eq!(std::fs::read(sink.join("file")).unwrap(), b"content");
+    assert_eq!(summary.ok, 1);
+    assert_eq!(summary.failures().map(|ack| ack.rel_path.as_path()).collect::<Vec<_>>(), vec![path::Path::new("blocked/file")]);
+    assert!(matches!(summary.issues[0].status, Status::IoError(_)));
+}
+
+#[test]
+fn aborted_entry() {
+    let dir = tempfile::tempdir().unwrap();
+    let source = dir.path().join("source");
+    let sink = dir.path().join("sink");
+    std::fs::write(&source, b"content").unwrap();
+
+    let (left, right) = crate::transport::pipe();
+    let sender = std::thread::spawn({
+        let (source, sink, vanished) = (source.clone(), sink.clone(), dir.path().join("vanished"));
+        move || {
+            let mut link = Link::new(left).with_checksum(Some(Box::new(checksum::Md5))).with_acks(true);
+            link.write_upload_metadata(3, 7, &sink).unwrap();
+            link.write_to_stream(&vanished, path::Path::new("vanished")).unwrap();
+            link.write_to_stream(&source, path::Path::new("file")).unwrap();
+            link.abort_session(error::Error::invalid_request("gone"));
+            link.outcomes().to_vec()
+        }
+    });
+
+    let mut link = Link::new(right);
+    let metadata = link.read_upload_metadata().unwrap();
+    link.read_from_stream(&metadata.destination, &metadata.eof_marker).unwrap();
+    link.read_from_stream(&metadata.destination, &metadata.eof_marker).unwrap();
+    let aborted = link.read_from_stream(&metadata.destination, &metadata.eof_marker);
+    let outcomes = sender.join().unwrap();
+
+    assert!(matches!(aborted, Err(error::Error::Aborted(Abort::Session(reason))) if reason == "gone"));
+    assert!(matches!(&outcomes[0].status, Status::IoError(reason) if reason.starts_with("aborted by the sender")));
+    assert_eq!(outcomes[1].status, Status::Ok);
+    assert_eq!(std::fs::read(sink.join("file")).unwrap(), b"content");
+    assert!(!sink.join("vanished").exists() && !temp_path(&sink.join("vanished")).exists());
+}
+
+#[test]
+fn over_quota() {
+    let dir = tempfile::tempdir().unwrap();
+    let (left, right) = crate::transport::pipe();
+    let mut sender = Link::new(left);
+    sender.write_upload_metadata(2, 9, dir.path()).unwrap();
+    for (name, content) in [("large", b"content".as_ref()), ("small", b"ok")] {
+        sender.write_file_metadata(&FileMetadata::new(path::Path::new(name)).with_size(Some(content.len() as u64))).unwrap();
+        sender.upstream(content).unwrap();
+        sender.upstream(EOF_MARKER.as_ref()).unwrap();
+    }
+
+    let quota = crate::quota::Quota { bytes: Some(3), files: None };
+    let mut link = Link::new(right).with_account(Some(Account::new(dir.path(), quota, crate::quota::Usage::default())));
+    let metadata = link.read_upload_metadata().unwrap();
+    for _ in 0..metadata.count {
+        link.read_from_stream(&metadata.destination, &metadata.eof_marker).unwrap();
+    }
+
+    assert!(matches!(link.outcomes()[0].status, Status::QuotaExceeded(_)));
+    assert_eq!(link.outcomes()[1].status, Status::Ok);
+    assert!(!dir.path().join("large").exists());
+    assert_eq!(std::fs::read(dir.path().join("small")).unwrap(), b"ok");
+}
+
+#[test]
+fn unset_attributes() {
+    let dir = tempfile::tempdir().unwrap();
+    let xattrs = crate::xattrs::Xattrs::from([("bogus.name".to_string(), b"value".to_vec())]);
+
+    let sink = dir.path().to_path_buf();
+    let ((), link, received) = transfer(move |mut link| {
+        link.write_upload_metadata(1, 2, &sink).unwrap();
+        link.write_file_metadata(&FileMetadata::new(path::Path::new("file")).with_xattrs(Some(xattrs))).unwrap();
+        link.upstream(b"ok").unwrap();
+        link.upstream(EOF_MARKER.as_ref()).unwrap();
+    }, |link| link);
+
+    assert_eq!(received.unwrap(), 1);
+    assert!(matches!(&link.outcomes()[0].status, Status::AttributesNotSet(unset) if unset.starts_with("bogus.name")));
+    assert_eq!(std::fs::read(dir.path().join("file")).unwrap(), b"ok");
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn followed_xattrs() {
+    let dir = tempfile::tempdir().unwrap();
+    let target = dir.path().join("target");
+    std::fs::write(&target, b"target").unwrap();
+    std::os::unix::fs::symlink(&target, dir.path().join("link")).unwrap();
+
+    // not every filesystem takes user attributes
+    let xattrs = crate::xattrs::Xattrs::from([("user.atilink".to_string(), b"value".to_vec())]);
+    if !crate::xattrs::apply(&std::fs::File::open(&target).unwrap(), &xattrs).is_empty() {
+        return;
+    }
+
+    let mut link = Link::new(crate::transport::pipe().0).with_symlinks(SymlinkPolicy::Follow).with_preserve(Preserve { xattrs: true, ..Preserve::default() });
+    let (file_metadata, _) = link.describe(&dir.path().join("link"), path::Path::new("link")).unwrap();
+    assert_eq!(file_metadata.xattrs, Some(xattrs));
+}
+
+#[test]
+fn tree_round_trip() {
+    let dir = tempfile::tempdir().unwrap();
+    let source = dir.path().join("source");
+    let sink = dir.path().join("sink");
+    std::fs::create_dir_all(source.join("empty/nested")).unwrap();
+    std::fs::create_dir_all(source.join("full")).unwrap();
+    std::fs::write(source.join("full/file"), b"file").unwrap();
+    #[cfg(unix)]
+    {
+        std::os::unix::fs::symlink("file", source.join("full/link")).unwrap();
+        std::os::unix::fs::symlink("../../outside", source.join("full/escape")).unwrap();
+        std::fs::hard_link(source.join("full/file"), source.join("hard")).unwrap();
+    }
+
+    let (left, right) = crate::transport::pipe();
+    let paths = crate::get_recursive_paths(&source, SymlinkPolicy::CopyLink);
+    let count = paths.len();
+    let sender = std::thread::spawn({
+        let (source, sink) = (source.clone(), sink.clone());
+        move || {
+            let mut link = Link::new(left).with_symlinks(SymlinkPolicy::CopyLink);
+            link.write_upload_metadata(count, 0, &sink).unwrap();
+            for path in paths {
+                link.write_to_stream(&path, path.strip_prefix(&source).unwrap()).unwrap();
+            }
+        }
+    });
+
+    let mut link = Link::new(right).with_confined_links(true);
+    let metadata = link.read_upload_metadata().unwrap();
+    for _ in 0..metadata.count {
+        link.read_from_stream(&metadata.destination, &metadata.eof_marker).unwrap();
+    }
+    sender.join().unwrap();
+
+    assert!(sink.join("empty/nested").is_dir());
+    assert_eq!(std::fs::read(sink.join("full/file")).unwrap(), b"file");
+    #[cfg(unix)]
+    {
+        assert_eq!(std::fs::read_link(sink.join("full/link")).unwrap(), path::Path::new("file"));
+        assert!(std::fs::symlink_metadata(sink.join("full/escape")).is_err());
+
+        use std::os::unix::fs::MetadataExt;
+        assert_eq!(std::fs::metadata(sink.join("hard")).unwrap().ino(), std::fs::metadata(sink.join("full/file")).unwrap().ino());
+    }
+}
+
+#[test]
+fn negotiated_tree() {
+    /// sends `changed`, `new` and `same` from `source` to `sink` as the client does, returns the plan of the sender and the count of the receiver
+    fn offer(source: &path::Path, sink: &path::Path, quick_check: Option<QuickCheck>, conflict: Conflict, dry_run: bool) -> (Result<Vec<Action>, error::Error>, Result<usize, error::Error>) {
+        let entries = crate::relative_entries(source, ["changed", "new", "same"].map(|name| source.join(name)).to_vec()).unwrap();
+        let sink = sink.to_path_buf();
+        let (plan, _, received) = transfer(move |link| {
+            let mut link = link.with_quick_check(quick_check).with_conflict(conflict).with_dry_run(dry_run);
+            link.write_upload_metadata(entries.len(), 0, &sink)?;
+            for (file, rel) in link.negotiate(entries)? {
+                link.write_to_stream(&file, &rel)?;
+            }
+            Ok(link.plan().iter().map(|(_, action)| action.clone()).collect())
+        }, |link| link);
+        (plan, received)
+    }
+
+    let dir = tempfile::tempdir().unwrap();
+    let source = dir.path().join("source");
+    std::fs::create_dir_all(&source).unwrap();
+    std::fs::write(source.join("changed"), b"new content").unwrap();
+    std::fs::write(source.join("new"), b"new").unwrap();
+    std::fs::write(source.join("same"), b"same").unwrap();
+    let sinks = ["renamed", "failed"].map(|name| dir.path().join(name));
+    for sink in &sinks {
+        std::fs::create_dir_all(sink).unwrap();
+        std::fs::write(sink.join("changed"), b"old").unwrap();
+        std::fs::write(sink.join("same"), b"same").unwrap();
+    }
+
+    let (plan, received) = offer(&source, &sinks[0], Some(QuickCheck::Digest), Conflict::RenameNew, false);
+    assert_eq!(plan.unwrap(), [Action::Rename { to: "changed.1".into() }, Action::Create, Action::Skip]);
+    assert_eq!(received.unwrap(), 2);
+    assert_eq!(std::fs::read(sinks[0].join("changed")).unwrap(), b"old");
+    assert_eq!(std::fs::read(sinks[0].join("changed.1")).unwrap(), b"new content");
+    assert_eq!(std::fs::read(sinks[0].join("new")).unwrap(), b"new");
+
+    // a dry run only plans, even what would fail
+    let (plan, received) = offer(&source, &sinks[1], None, Conflict::Fail, true);
+    assert_eq!(plan.unwrap(), [Action::Fail, Action::Create, Action::Fail]);
+    assert_eq!(received.unwrap(), 0);
+
+    let (plan, received) = offer(&source, &sinks[1], None, Conflict::Fail, false);
+    assert!(plan.is_err() && received.is_err());
+    assert!(!sinks[1].join("new").exists());
+    assert_eq!(std::fs::read(sinks[1].join("changed")).unwrap(), b"old");
+}
+
+#[test]
+fn refused_dry_run() {
+    let dir = tempfile::tempdir().unwrap();
+    std::fs::write(dir.path().join("file"), b"content").unwrap();
+    let sink = dir.path().join("sink");
+
+    let (refusal, _, received) = transfer({
+        let (source, sink) = (dir.path().join("file"), sink.clone());
+        move |link| {
+            let mut link = link.with_dry_run(true);
+            link.write_upload_metadata(1, 7, &sink).unwrap();
+            assert!(link.negotiate(vec![(source, "file".into())]).unwrap().is_empty());
+            link.refusal().map(str::to_string)
+        }
+    }, |link| link.with_refusal(Some("not enough space".to_string())));
+
+    assert_eq!(received.unwrap(), 0);
+    assert_eq!(refusal.as_deref(), Some("not enough space"));
+    assert!(!sink.exists());
+}
+
+#[cfg(unix)]
+#[test]
+fn links_confined() {
+    let dir = tempfile::tempdir().unwrap();
+    let sink = dir.path().join("sink");
+    let outside = dir.path().join("outside");
+    std::fs::create_dir_all(&sink).unwrap();
+    std::fs::create_dir_all(&outside).unwrap();
+    std::fs::write(outside.join("secret"), b"secret").unwrap();
+    std::os::unix::fs::symlink(&outside, sink.join("out")).unwrap();
+
+    let (left, right) = crate::transport::pipe();
+    let mut sender = Link::new(left);
+    sender.write_upload_metadata(5, 1, &sink).unwrap();
+    let link = |rel: &str, target: &str| FileMetadata::new(path::Path::new(rel)).with_kind(EntryKind::Symlink { target: target.into() });
+    sender.write_file_metadata(&link("dir/up", "..")).unwrap();
+    sender.write_file_metadata(&link("dir/up/up2", "..")).unwrap();
+    sender.write_file_metadata(&FileMetadata::new(path::Path::new("out/x"))).unwrap();
+    sender.upstream(b"x").unwrap();
+    sender.upstream(EOF_MARKER.as_ref()).unwrap();
+    sender.write_file_metadata(&FileMetadata::new(path::Path::new("hard")).with_kind(EntryKind::HardLink { target: "out/secret".into() })).unwrap();
+    sender.write_file_metadata(&FileMetadata::new(path::Path::new("out")).with_kind(EntryKind::Directory)).unwrap();
+
+    let mut link = Link::new(right).with_confined_links(true);
+    let metadata = link.read_upload_metadata().unwrap();
+    for _ in 0..metadata.count {
+        link.read_from_stream(&metadata.destination, &metadata.eof_marker).unwrap();
+    }
+
+    let statuses = link.outcomes().iter().map(|ack| matches!(ack.status, Status::Ok)).collect::<Vec<bool>>();
+    assert_eq!(statuses, [true, false, false, false, false]);
+    assert!(std::fs::symlink_metadata(sink.join("up2")).is_err());
+    assert_eq!(std::fs::read_dir(&outside).unwrap().count(), 1);
+    assert!(!sink.join("hard").exists());
+}
@@ -0,0 +1,308 @@
+use std::{collections::HashMap, fs, io::{Read, Seek, SeekFrom, Write}};
+use super::error::Error;
+
+const MIN_BLOCK: usize = 2 * 1024;
+const MAX_BLOCK: usize = 128 * 1024;
+
+/// Signature of one block of the receiver's copy
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct Block {
+    /// rolling checksum, see [`Rolling`]
+    pub weak: u32,
+    /// md5 of the block, confirms a weak match
+    pub strong: [u8; 16],
+}
+
+/// Block signatures of the receiver's copy of a file, sent to the sender
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct Signature {
+    pub block_size: u32,
+    /// every block is `block_size` long but the last one
+    pub blocks: Vec<Block>,
+    /// length of the last block
+    pub last_len: u32,
+}
+
+impl Signature {
+    /// signs `file` in blocks of about the square root of its size
+    pub fn read(file: &mut fs::File) -> Result<Self, Error> {
+        let len = file.metadata()?.len();
+        let block_size = ((len as f64).sqrt() as usize).clamp(MIN_BLOCK, MAX_BLOCK);
+        let mut reader = std::io::BufReader::new(file);
+        let mut buffer = vec![0; block_size];
+        let mut blocks = Vec::new();
+        let mut last_len = 0;
+
+        loop {
+            let n = read_full(&mut reader, &mut buffer)?;
+            if n == 0 {
+                break;
+            }
+            blocks.push(Block { weak: Rolling::new(&buffer[..n]).digest(), strong: md5::compute(&buffer[..n]).0 });
+            last_len = n as u32;
+        }
+
+        Ok(Self { block_size: block_size as u32, blocks, last_len })
+    }
+
+    fn block_len(&self, index: usize) -> usize {
+        match index + 1 == self.blocks.len() {
+            true => self.last_len as usize,
+            false => self.block_size as usize,
+        }
+    }
+}
+
+/// How the receiver rebuilds a file out of its own copy and what the sender sends
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub enum Instruction {
+    /// `count` blocks of the receiver's copy starting at block `index`
+    Copy { index: u64, count: u64 },
+    /// bytes the receiver doesn't have
+    Literal(Vec<u8>),
+}
+
+/// rsync's rolling checksum, sliding over a window one byte at a time
+#[derive(Debug, Clone, Copy)]
+pub struct Rolling {
+    a: u32,
+    b: u32,
+    len: u32,
+}
+
+impl Rolling {
+    pub fn new(window: &[u8]) -> Self {
+        let (mut a, mut b) = (0u32, 0u32);
+        for (i, byte) in window.iter().enumerate() {
+            a = a.wrapping_add(*byte as u32);
+            b = b.wrapping_add((window.len() - i) as u32 * *byte as u32);
+        }
+        Self { a, b, len: window.len() as u32 }
+    }
+
+    /// moves the window one byte forward, dropping `out` and taking in `incoming`
+    pub fn roll(&mut self, out: u8, incoming: u8) {
+        self.a = self.a.wrapping_sub(out as u32).wrapping_add(incoming as u32);
+        self.b = self.b.wrapping_sub(self.len.wrapping_mul(out as u32)).wrapping_add(self.a);
+    }
+
+    pub fn digest(&self) -> u32 {
+        (self.a & 0xffff) | (self.b << 16)
+    }
+}
+
+/// Turns the sender's copy into [`Instruction`]s against a [`Signature`] of the receiver's copy
+pub struct Diff<'a> {
+    signature: &'a Signature,
+    /// weak checksum to the blocks having it
+    index: HashMap<u32, Vec<usize>>,
+}
+
+impl<'a> Diff<'a> {
+    pub fn new(signature: &'a Signature) -> Self {
+        let mut index: HashMap<u32, Vec<usize>> = HashMap::new();
+        // the short last block can only match at the very end, it's checked separately
+        for (i, block) in signature.blocks.iter().enumerate().take(signature.blocks.len().saturating_sub(1)) {
+            index.entry(block.weak).or_default().push(i);
+        }
+        if let Some(last) = signature.blocks.last().filter(|_| signature.last_len == signature.block_size) {
+            index.entry(last.weak).or_default().push(signature.blocks.len() - 1);
+        }
+
+        Self { signature, index }
+    }
+
+    /// block of the receiver's copy holding `window`
+    fn find(&self, weak: u32, window: &[u8]) -> Option<usize> {
+        let candidates = self.index.get(&weak)?;
+        let strong = md5::compute(window).0;
+        candidates.iter().copied().find(|i| self.signature.blocks[*i].strong == strong)
+    }
+
+    /// reads `reader` to the end and hands out instructions in batches of about `batch` bytes
+    pub fn run(&self, mut reader: impl Read, batch: usize, mut emit: impl FnMut(&[Instruction]) -> Result<(), Error>) -> Result<(), Error> {
+        let block = self.signature.block_size as usize;
+        let mut batcher = Batcher { pending: Vec::new(), bytes: 0, batch };
+        // bytes before `literal` are dealt with, `pos` is the start of the window
+        let mut data = Vec::new();
+        let (mut literal, mut pos) = (0, 0);
+        let mut rolling: Option<Rolling> = None;
+        let mut eof = false;
+
+        loop {
+            if !eof && data.len() - pos < block {
+                data.drain(..literal);
+                pos -= literal;
+                literal = 0;
+
+                let start = data.len();
+                data.resize(start + block.max(64 * 1024), 0);
+                let n = reader.read(&mut data[start..])?;
+                data.truncate(start + n);
+                eof = n == 0;
+                continue;
+            }
+
+            let remaining = data.len() - pos;
+            if remaining < block {
+                // end of the file, only the last block of the receiver can still match
+                let window = &data[pos..];
+                let last = self.signature.blocks.len().checked_sub(1);
+                let matched = last.filter(|i| {
+                    let b = &self.signature.blocks[*i];
+                    !window.is_empty() && self.signature.block_len(*i) == window.len() && b.weak == Rolling::new(window).digest() && b.strong == md5::compute(window).0
+                });
+
+                match matched {
+                    Some(i) => {
+                        batcher.literal(&data[literal..pos], &mut emit)?;
+                        batcher.copy(i as u64, &mut emit)?;
+                    },
+                    None => batcher.literal(&data[literal..], &mut emit)?,
+                }
+                break;
+            }
+
+            let window = &data[pos..pos + block];
+            let weak = rolling.get_or_insert_with(|| Rolling::new(window)).digest();
+            match self.find(weak, window) {
+                Some(i) => {
+                    batcher.literal(&data[literal..pos], &mut emit)?;
+                    batcher.copy(i as u64, &mut emit)?;
+                    pos += block;
+                    literal = pos;
+                    rolling = None;
+                },
+                None => {
+                    match (rolling.as_mut(), data.get(pos + block)) {
+                        (Some(r), Some(incoming)) => r.roll(data[pos], *incoming),
+                        _ => rolling = None,
+                    }
+                    pos += 1;
+                    if pos - literal >= batch {
+                        batcher.literal(&data[literal..pos], &mut emit)?;
+                        literal = pos;
+                    }
+                },
+            }
+        }
+
+        batcher.flush(&mut emit)
+    }
+}
+
+/// Gathers instructions until they are worth a chunk
+struct Batcher {
+    pending: Vec<Instruction>,
+    /// literal bytes pending
+    bytes: usize,
+    batch: usize,
+}
+
+impl Batcher {
+    fn literal(&mut self, bytes: &[u8], emit: &mut impl FnMut(&[Instruction]) -> Result<(), Error>) -> Result<(), Error> {
+        if bytes.is_empty() {
+            return Ok(());
+        }
+        self.pending.push(Instruction::Literal(bytes.to_vec()));
+        self.bytes += bytes.len();
+        match self.bytes >= self.batch {
+            true => self.flush(emit),
+            false => Ok(()),
+        }
+    }
+
+    fn copy(&mut self, index: u64, emit: &mut impl FnMut(&[Instruction]) -> Result<(), Error>) -> Result<(), Error> {
+        // consecutive blocks are merged into one instruction
+        if let Some(Instruction::Copy { index: start, count }) = self.pending.last_mut() {
+            if *start + *count == index {
+                *count += 1;
+                return Ok(());
+            }
+        }
+        self.pending.push(Instruction::Copy { index, count: 1 });
+        // keeps the batches of an unchanged file bounded
+        match self.pending.len() >= 64 * 1024 {
+            true => self.flush(emit),
+            false => Ok(()),
+        }
+    }
+
+    fn flush(&mut self, emit: &mut impl FnMut(&[Instruction]) -> Result<(), Error>) -> Result<(), Error> {
+        if !self.pending.is_empty() {
+            emit(&self.pending)?;
+            self.pending.clear();
+            self.bytes = 0;
+        }
+        Ok(())
+    }
+}
+
+/// writes the result of `instructions` to `out`, copied blocks are read from `basis`
+pub fn patch(basis: &mut fs::File, signature: &Signature, instructions: &[Instruction], out: &mut impl Write) -> Result<(), Error> {
+    let block = signature.block_size as u64;
+    let mut buffer = Vec::new();
+
+    for instruction in instructions {
+        match instruction {
+            Instruction::Literal(bytes) => out.write_all(bytes)?,
+            Instruction::Copy { index, count } => {
+                let end = index + count;
+                if end > signature.blocks.len() as u64 {
+                    return Err(Error::integrity_error("copy instruction past the end of the file"));
+                }
+                let len = (count - 1) * block + signature.block_len(end as usize - 1) as u64;
+
+                basis.seek(SeekFrom::Start(index * block))?;
+                buffer.resize(len as usize, 0);
+                basis.read_exact(&mut buffer)?;
+                out.write_all(&buffer)?;
+            },
+        }
+    }
+
+    Ok(())
+}
+
+/// reads until `buffer` is full or the end of the reader
+fn read_full(reader: &mut impl Read, buffer: &mut [u8]) -> Result<usize, Error> {
+    let mut n = 0;
+    while n < buffer.len() {
+        match reader.read(&mut buffer[n..])? {
+            0 => break,
+            read => n += read,
+        }
+    }
+    Ok(n)
+}
+
+#[test]
+fn delta_test() {
+    let dir = tempfile::tempdir().unwrap();
+    let old = (0..1_000_000u32).map(|i| (i.wrapping_mul(2654435761) >> 13) as u8).collect::<Vec<u8>>();
+    let mut new = old.clone();
+    new[500_000..500_100].fill(0);
+    new.splice(10..10, *b"inserted");
+    new.truncate(990_000);
+
+    let basis_path = dir.path().join("basis");
+    fs::write(&basis_path, &old).unwrap();
+    let mut basis = fs::File::open(&basis_path).unwrap();
+    let signature = Signature::read(&mut basis).unwrap();
+
+    let mut instructions = Vec::new();
+    Diff::new(&signature).run(new.as_slice(), 64 * 1024, |batch| {
+        instructions.extend_from_slice(batch);
+        Ok(())
+    }).unwrap();
+
+    let literal = instructions.iter().map(|i| match i {
+        Instruction::Literal(bytes) => bytes.len(),
+        Instruction::Copy { .. } => 0,
+    }).sum::<usize>();
+    assert!(literal < 4 * signature.block_size as usize, "{literal} literal bytes");
+
+    let mut out = Vec::new();
+    patch(&mut basis, &signature, &instructions, &mut out).unwrap();
+    assert_eq!(out, new);
+}
@@ -10,6 +10,8 @@ pub enum Error {
     IntegrityError(String),
     DownloadError(String),
     InvalidRequest(String),
+    /// the peer sent an abort frame
+    Aborted(super::Abort),
 }
 
 impl std::error::Error for Error {}
@@ -25,6 +27,7 @@ impl std::fmt::Display for Error {
             Self::IntegrityError(s) => write!(f, "{s}"),
             Self::DownloadError(s) => write!(f, "{s}"),
             Self::InvalidRequest(s) => write!(f, "{s}"),
+            Self::Aborted(abort) => write!(f, "{abort}"),
         }
     }
 }
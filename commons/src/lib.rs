@@ -1,18 +1,34 @@
+pub mod ack;
+pub mod attributes;
 pub mod checksum;
 pub mod compression;
+pub mod delta;
 pub mod error;
+pub mod manifest;
 pub mod connection;
+pub mod mirror;
+pub mod quick_check;
+pub mod quota;
+pub mod sparse;
+pub mod throttle;
+pub mod transport;
+pub mod xattrs;
+#[cfg(feature = "async")]
+pub mod async_connection;
 
 use std::sync::LazyLock;
 use rand::{rngs::OsRng, RngCore};
 
-pub static EOF_MARKER: LazyLock<[u8; CHUNK]> = LazyLock::new(generate_eof_marker);
-pub const CHUNK: usize = 1 * 1000 * 1000; // 1mb
+pub static EOF_MARKER: LazyLock<Vec<u8>> = LazyLock::new(generate_eof_marker);
+pub const CHUNK: usize = 1000 * 1000; // 1mb
+/// length announcing an [`Abort`] in place of a chunk, no chunk is ever that long
+pub const ABORT_FRAME: u32 = u32::MAX;
 
-fn generate_eof_marker() -> [u8; CHUNK] {
+fn generate_eof_marker() -> Vec<u8> {
     println!("GENERATING MARKER");
     let mut rng = OsRng;
-    let mut marker = [0u8; CHUNK];
+    // kept on the heap, a CHUNK sized array overflows the stack of smaller (test, async) threads
+    let mut marker = vec![0u8; CHUNK];
     rng.fill_bytes(&mut marker);
     marker
 }
@@ -21,12 +37,60 @@ fn generate_eof_marker() -> [u8; CHUNK] {
 #[derive(Debug, serde::Serialize, serde::Deserialize)]
 pub struct FileMetadata {
     pub rel_path: std::path::PathBuf,
+    pub kind: EntryKind,
+    pub attributes: attributes::Attributes,
+    /// data regions of a sparse file, `None` when the whole content is sent
+    pub extents: Option<sparse::Extents>,
+    /// extended attributes, sent when requested by [`Preserve`]
+    pub xattrs: Option<xattrs::Xattrs>,
+    /// size of a regular file, the receiver reserves it before the content arrives
+    pub size: Option<u64>,
 }
 
 impl FileMetadata {
     pub fn new(path: &std::path::Path) -> Self {
         Self {
             rel_path: path.to_path_buf(),
+            kind: EntryKind::File,
+            attributes: attributes::Attributes::default(),
+            extents: None,
+            xattrs: None,
+            size: None,
+        }
+    }
+
+    pub fn with_kind(self, kind: EntryKind) -> Self {
+        Self {
+            kind,
+            ..self
+        }
+    }
+
+    pub fn with_attributes(self, attributes: attributes::Attributes) -> Self {
+        Self {
+            attributes,
+            ..self
+        }
+    }
+
+    pub fn with_extents(self, extents: Option<sparse::Extents>) -> Self {
+        Self {
+            extents,
+            ..self
+        }
+    }
+
+    pub fn with_xattrs(self, xattrs: Option<xattrs::Xattrs>) -> Self {
+        Self {
+            xattrs,
+            ..self
+        }
+    }
+
+    pub fn with_size(self, size: Option<u64>) -> Self {
+        Self {
+            size,
+            ..self
         }
     }
 
@@ -39,24 +103,212 @@ impl FileMetadata {
     }
 }
 
+/// Kind of an entry of the transferred tree
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub enum EntryKind {
+    /// regular file, its content follows as chunks ending with the eof marker
+    File,
+    /// directory to be created, nothing follows
+    Directory,
+    /// symbolic link to be created, nothing follows
+    Symlink {
+        target: std::path::PathBuf,
+    },
+    /// hard link to a file sent earlier in the session, `target` is relative to the destination
+    HardLink {
+        target: std::path::PathBuf,
+    },
+}
+
+/// What the receiver does with a file that already exists at the destination
+#[derive(Debug, Default, Clone, Copy, PartialEq, serde::Serialize, serde::Deserialize)]
+pub enum Conflict {
+    #[default]
+    Overwrite,
+    /// keep the existing file
+    SkipExisting,
+    /// replace the existing file only when the incoming one was modified later
+    NewerOnly,
+    /// write the incoming file next to the existing one, under a numbered suffix
+    RenameNew,
+    /// abort the transfer
+    Fail,
+}
+
+impl std::str::FromStr for Conflict {
+    type Err = error::Error;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "overwrite" => Ok(Self::Overwrite),
+            "skip-existing" => Ok(Self::SkipExisting),
+            "newer-only" => Ok(Self::NewerOnly),
+            "rename-new" => Ok(Self::RenameNew),
+            "fail" => Ok(Self::Fail),
+            _ => Err(error::Error::InvalidArgument(format!("invalid conflict policy {s}"))),
+        }
+    }
+}
+
+/// When the receiver flushes what it wrote to disk
+#[derive(Debug, Default, Clone, Copy, PartialEq, serde::Serialize, serde::Deserialize)]
+pub enum Fsync {
+    /// left to the operating system
+    #[default]
+    Never,
+    /// every file before it is renamed into place, and its directory after
+    PerFile,
+    /// every file and directory written, once the session is over
+    End,
+}
+
+impl std::str::FromStr for Fsync {
+    type Err = error::Error;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "never" => Ok(Self::Never),
+            "per-file" => Ok(Self::PerFile),
+            "end" => Ok(Self::End),
+            _ => Err(error::Error::InvalidArgument(format!("invalid fsync policy {s}"))),
+        }
+    }
+}
+
+/// How symbolic links within the transferred tree are treated
+#[derive(Debug, Default, Clone, Copy, PartialEq, serde::Serialize, serde::Deserialize)]
+pub enum SymlinkPolicy {
+    /// links are left out
+    #[default]
+    Skip,
+    /// links are recreated as links on the receiver
+    CopyLink,
+    /// links are replaced by what they point to
+    Follow,
+}
+
+impl std::str::FromStr for SymlinkPolicy {
+    type Err = error::Error;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "skip" => Ok(Self::Skip),
+            "copy-link" => Ok(Self::CopyLink),
+            "follow" => Ok(Self::Follow),
+            _ => Err(error::Error::InvalidArgument(format!("invalid symlink policy {s}"))),
+        }
+    }
+}
+
 /// Metadata sent before the initiation of file transfer
 #[derive(Debug, serde::Serialize, serde::Deserialize)]
 pub struct UploadMetadata {
     pub count: u32,
+    /// size of every regular file offered, the receiver checks it has room for them
+    pub bytes: u64,
     pub destination: std::path::PathBuf,
     pub eof_marker: Vec<u8>,
     pub compression: Option<Compression>,
     pub checksum: Option<Checksum>,
+    pub preserve: Preserve,
+    pub quick_check: Option<quick_check::QuickCheck>,
+    pub delta: bool,
+    pub mirror: Option<mirror::Mirror>,
+    pub dry_run: bool,
+    pub conflict: Conflict,
+    pub fsync: Fsync,
+    pub manifest: Option<manifest::Detail>,
+    pub acks: bool,
 }
 
 impl UploadMetadata {
     pub fn new(count: u32, destination: &std::path::Path) -> Self {
         Self {
             count,
+            bytes: 0,
             destination: destination.to_path_buf(),
             eof_marker: EOF_MARKER.to_vec(),
             compression: None,
             checksum: None,
+            preserve: Preserve::default(),
+            quick_check: None,
+            delta: false,
+            mirror: None,
+            dry_run: false,
+            conflict: Conflict::default(),
+            fsync: Fsync::default(),
+            manifest: None,
+            acks: false,
+        }
+    }
+
+    pub fn with_preserve(self, preserve: Preserve) -> Self {
+        Self {
+            preserve,
+            ..self
+        }
+    }
+
+    pub fn with_quick_check(self, quick_check: Option<quick_check::QuickCheck>) -> Self {
+        Self {
+            quick_check,
+            ..self
+        }
+    }
+
+    pub fn with_delta(self, delta: bool) -> Self {
+        Self {
+            delta,
+            ..self
+        }
+    }
+
+    pub fn with_mirror(self, mirror: Option<mirror::Mirror>) -> Self {
+        Self {
+            mirror,
+            ..self
+        }
+    }
+
+    pub fn with_dry_run(self, dry_run: bool) -> Self {
+        Self {
+            dry_run,
+            ..self
+        }
+    }
+
+    pub fn with_conflict(self, conflict: Conflict) -> Self {
+        Self {
+            conflict,
+            ..self
+        }
+    }
+
+    pub fn with_fsync(self, fsync: Fsync) -> Self {
+        Self {
+            fsync,
+            ..self
+        }
+    }
+
+    pub fn with_manifest(self, manifest: Option<manifest::Detail>) -> Self {
+        Self {
+            manifest,
+            ..self
+        }
+    }
+
+    pub fn with_acks(self, acks: bool) -> Self {
+        Self {
+            acks,
+            ..self
+        }
+    }
+
+    pub fn with_bytes(self, bytes: u64) -> Self {
+        Self {
+            bytes,
+            ..self
         }
     }
 
@@ -89,6 +341,16 @@ pub struct DownloadMetadata {
     pub destination: std::path::PathBuf,
     pub compression: Option<Compression>,
     pub checksum: Option<Checksum>,
+    pub preserve: Preserve,
+    pub symlinks: SymlinkPolicy,
+    pub quick_check: Option<quick_check::QuickCheck>,
+    pub delta: bool,
+    pub mirror: Option<mirror::Mirror>,
+    pub dry_run: bool,
+    pub conflict: Conflict,
+    pub fsync: Fsync,
+    pub manifest: Option<manifest::Detail>,
+    pub acks: bool,
 }
 
 impl DownloadMetadata {
@@ -97,6 +359,86 @@ impl DownloadMetadata {
             destination: destination.to_path_buf(),
             compression: None,
             checksum: None,
+            preserve: Preserve::default(),
+            symlinks: SymlinkPolicy::default(),
+            quick_check: None,
+            delta: false,
+            mirror: None,
+            dry_run: false,
+            conflict: Conflict::default(),
+            fsync: Fsync::default(),
+            manifest: None,
+            acks: false,
+        }
+    }
+
+    pub fn with_symlinks(self, symlinks: SymlinkPolicy) -> Self {
+        Self {
+            symlinks,
+            ..self
+        }
+    }
+
+    pub fn with_quick_check(self, quick_check: Option<quick_check::QuickCheck>) -> Self {
+        Self {
+            quick_check,
+            ..self
+        }
+    }
+
+    pub fn with_delta(self, delta: bool) -> Self {
+        Self {
+            delta,
+            ..self
+        }
+    }
+
+    pub fn with_mirror(self, mirror: Option<mirror::Mirror>) -> Self {
+        Self {
+            mirror,
+            ..self
+        }
+    }
+
+    pub fn with_dry_run(self, dry_run: bool) -> Self {
+        Self {
+            dry_run,
+            ..self
+        }
+    }
+
+    pub fn with_conflict(self, conflict: Conflict) -> Self {
+        Self {
+            conflict,
+            ..self
+        }
+    }
+
+    pub fn with_fsync(self, fsync: Fsync) -> Self {
+        Self {
+            fsync,
+            ..self
+        }
+    }
+
+    pub fn with_manifest(self, manifest: Option<manifest::Detail>) -> Self {
+        Self {
+            manifest,
+            ..self
+        }
+    }
+
+    pub fn with_acks(self, acks: bool) -> Self {
+        Self {
+            acks,
+            ..self
+        }
+    }
+
+    pub fn with_preserve(self, preserve: Preserve) -> Self {
+        Self {
+            preserve,
+            ..self
         }
     }
 
@@ -143,6 +485,24 @@ impl Result {
     }
 }
 
+/// Sent by the sender in place of the next chunk when it can't go on, the reason follows
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub enum Abort {
+    /// the entry being sent is dropped, the next ones follow
+    Entry(String),
+    /// nothing more follows
+    Session(String),
+}
+
+impl std::fmt::Display for Abort {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Entry(reason) => write!(f, "aborted by the sender, {reason}"),
+            Self::Session(reason) => write!(f, "session aborted by the peer, {reason}"),
+        }
+    }
+}
+
 #[derive(Debug, serde::Serialize, serde::Deserialize)]
 pub enum Compression {
     Zlib,
@@ -173,6 +533,39 @@ impl Checksum {
     }
 }
 
+/// File attributes carried over to the receiver
+#[derive(Debug, Default, Clone, Copy, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct Preserve {
+    /// unix mode bits
+    pub mode: bool,
+    /// modification and access times
+    pub times: bool,
+    /// owner and group, applied only when the receiver runs as root
+    pub owner: bool,
+    /// extended attributes, POSIX ACLs and SELinux labels included
+    pub xattrs: bool,
+}
+
+/// Parses a comma separated list like `mode,times,owner`
+impl std::str::FromStr for Preserve {
+    type Err = error::Error;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        let mut preserve = Self::default();
+        for attribute in s.split(',').map(str::trim).filter(|a| !a.is_empty()) {
+            match attribute {
+                "mode" => preserve.mode = true,
+                "times" => preserve.times = true,
+                "owner" => preserve.owner = true,
+                "xattrs" => preserve.xattrs = true,
+                "all" => preserve = Self { mode: true, times: true, owner: true, xattrs: true },
+                _ => return Err(error::Error::InvalidArgument(format!("cannot preserve {attribute}"))),
+            }
+        }
+        Ok(preserve)
+    }
+}
+
 /// The role assigned to the server
 #[derive(Debug, serde::Serialize, serde::Deserialize, Copy, Clone)]
 pub enum Role {
@@ -190,19 +583,236 @@ impl Role {
     }
 }
 
-/// method to recursively get all the files in the directory tree
-pub fn get_recursive_paths(path: &std::path::Path) -> Vec<std::path::PathBuf> {
+/// method to recursively get all the files and directories in the directory tree.
+/// Directories are listed after their content, so the receiver can apply their attributes once they are filled.
+/// `path` itself is always followed, `symlinks` decides what happens to links within the tree.
+pub fn get_recursive_paths(path: &std::path::Path, symlinks: SymlinkPolicy) -> Vec<std::path::PathBuf> {
     let mut files = Vec::new();
-    let metadata = std::fs::symlink_metadata(path).unwrap();
+    walk(path, symlinks, true, &mut Vec::new(), &mut files);
+    files
+}
+
+/// `ancestors` holds the directories being walked, a followed link pointing to one of them is a cycle
+fn walk(path: &std::path::Path, symlinks: SymlinkPolicy, root: bool, ancestors: &mut Vec<(u64, u64)>, files: &mut Vec<std::path::PathBuf>) {
+    let mut metadata = match std::fs::symlink_metadata(path) {
+        Ok(m) => m,
+        Err(e) => {
+            tracing::warn!("skipping {}. {e}", path.display());
+            return;
+        },
+    };
+
+    if metadata.is_symlink() {
+        match (root, symlinks) {
+            (false, SymlinkPolicy::Skip) => {
+                tracing::info!("skipping symlink {}", path.display());
+                return;
+            },
+            (false, SymlinkPolicy::CopyLink) => {
+                files.push(std::path::PathBuf::from(path));
+                return;
+            },
+            _ => match std::fs::metadata(path) {
+                Ok(m) => metadata = m,
+                Err(e) => {
+                    tracing::warn!("skipping broken symlink {}. {e}", path.display());
+                    return;
+                },
+            },
+        }
+    }
+
     if metadata.is_file() {
         files.push(std::path::PathBuf::from(path));
     }
     else if metadata.is_dir() {
-        for file in std::fs::read_dir(path).unwrap() {
-            let path = file.unwrap().path();
-            files.extend(get_recursive_paths(&path));
+        let id = file_id(&metadata);
+        if ancestors.contains(&id) {
+            tracing::warn!("skipping {}, following it leads to a cycle", path.display());
+            return;
         }
+
+        ancestors.push(id);
+        match std::fs::read_dir(path) {
+            Ok(entries) => for file in entries.flatten() {
+                walk(&file.path(), symlinks, false, ancestors, files);
+            },
+            Err(e) => tracing::warn!("unable to read directory {}. {e}", path.display()),
+        }
+        ancestors.pop();
+        files.push(std::path::PathBuf::from(path));
     }
+}
 
-    files
+/// (device, inode) of a file
+#[cfg(unix)]
+pub(crate) fn file_id(metadata: &std::fs::Metadata) -> (u64, u64) {
+    use std::os::unix::fs::MetadataExt;
+    (metadata.dev(), metadata.ino())
+}
+
+#[cfg(not(unix))]
+pub(crate) fn file_id(_metadata: &std::fs::Metadata) -> (u64, u64) {
+    (0, 0)
+}
+
+/// pairs every path of the tree under `root` with the path relative to it, as sent on the wire
+pub fn relative_entries(root: &std::path::Path, paths: Vec<std::path::PathBuf>) -> std::result::Result<Vec<(std::path::PathBuf, std::path::PathBuf)>, error::Error> {
+    paths.into_iter().map(|path| match path.strip_prefix(root) {
+        Ok(rel) => {
+            let rel = rel.to_path_buf();
+            Ok((path, rel))
+        },
+        Err(e) => Err(error::Error::InvalidRequest(format!("{} is not a relative path of {}, {e}", path.display(), root.display()))),
+    }).collect()
+}
+
+/// size of the regular files among `entries`, links are followed unless `symlinks` copies them
+pub fn total_size(entries: &[(std::path::PathBuf, std::path::PathBuf)], symlinks: SymlinkPolicy) -> u64 {
+    entries.iter().filter_map(|(path, _)| match symlinks {
+        SymlinkPolicy::CopyLink => std::fs::symlink_metadata(path).ok(),
+        _ => std::fs::metadata(path).ok(),
+    }).filter(std::fs::Metadata::is_file).map(|m| m.len()).sum()
+}
+
+/// bytes free for unprivileged users on the filesystem holding `path`, or where it would be created
+#[cfg(unix)]
+pub fn available_space(path: &std::path::Path) -> std::io::Result<u64> {
+    use std::os::unix::ffi::OsStrExt;
+
+    let existing = path.ancestors().find(|p| p.exists()).unwrap_or(std::path::Path::new("."));
+    let existing = std::ffi::CString::new(existing.as_os_str().as_bytes())?;
+    let mut stat = std::mem::MaybeUninit::<libc::statvfs>::uninit();
+    // SAFETY: `existing` is nul terminated and `stat` is valid for writes
+    if unsafe { libc::statvfs(existing.as_ptr(), stat.as_mut_ptr()) } < 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+
+    // SAFETY: statvfs succeeded and filled `stat`
+    let stat = unsafe { stat.assume_init() };
+    // the width of both fields varies across platforms
+    #[allow(clippy::unnecessary_cast)]
+    Ok(stat.f_bavail as u64 * stat.f_frsize as u64)
+}
+
+#[cfg(not(unix))]
+pub fn available_space(_path: &std::path::Path) -> std::io::Result<u64> {
+    Ok(u64::MAX)
+}
+
+/// parses sizes like `10GB`, `512KiB`, `1.5G` or plain bytes
+pub fn parse_size(s: &str) -> std::result::Result<u64, error::Error> {
+    let value = s.trim();
+    let split = value.find(|c: char| !c.is_ascii_digit() && c != '.').unwrap_or(value.len());
+    let (number, unit) = value.split_at(split);
+
+    let number = number.parse::<f64>().map_err(|_| error::Error::InvalidArgument(format!("invalid size {s}")))?;
+    let multiplier: u64 = match unit.trim() {
+        "" | "B" => 1,
+        "K" | "KB" => 1000,
+        "Ki" | "KiB" => 1 << 10,
+        "M" | "MB" => 1000 * 1000,
+        "Mi" | "MiB" => 1 << 20,
+        "G" | "GB" => 1000 * 1000 * 1000,
+        "Gi" | "GiB" => 1 << 30,
+        "T" | "TB" => 1000 * 1000 * 1000 * 1000,
+        "Ti" | "TiB" => 1 << 40,
+        _ => return Err(error::Error::InvalidArgument(format!("invalid size unit in {s}"))),
+    };
+
+    Ok((number * multiplier as f64) as u64)
+}
+
+/// absolute `path` with its links resolved, the part that doesn't exist yet is normalized as it will be created
+pub(crate) fn resolve(path: &std::path::Path) -> std::path::PathBuf {
+    let path = std::path::absolute(path).unwrap_or_else(|_| path.to_path_buf());
+    let Some((existing, mut resolved)) = path.ancestors().find_map(|a| std::fs::canonicalize(a).ok().map(|c| (a, c))) else {
+        return path;
+    };
+
+    for component in path.strip_prefix(existing).unwrap_or(std::path::Path::new("")).components() {
+        match component {
+            std::path::Component::Normal(name) => resolved.push(name),
+            std::path::Component::ParentDir => {
+                resolved.pop();
+            },
+            _ => {},
+        }
+    }
+
+    resolved
+}
+
+/// whether a link at `rel_path` pointing to `target` resolves outside of the tree it is created in
+pub fn escapes_tree(rel_path: &std::path::Path, target: &std::path::Path) -> bool {
+    let mut depth = rel_path.parent().map_or(0, |p| p.components().count());
+    for component in target.components() {
+        match component {
+            std::path::Component::Normal(_) => depth += 1,
+            std::path::Component::CurDir => {},
+            std::path::Component::ParentDir => match depth.checked_sub(1) {
+                Some(d) => depth = d,
+                None => return true,
+            },
+            std::path::Component::RootDir | std::path::Component::Prefix(_) => return true,
+        }
+    }
+
+    false
+}
+
+#[test]
+fn preserve_test() {
+    assert_eq!("mode,times".parse::<Preserve>().unwrap(), Preserve { mode: true, times: true, ..Preserve::default() });
+    assert_eq!("all".parse::<Preserve>().unwrap(), Preserve { mode: true, times: true, owner: true, xattrs: true });
+    assert!("mode,acl".parse::<Preserve>().is_err());
+}
+
+#[test]
+fn recursive_paths_test() {
+    let dir = tempfile::tempdir().unwrap();
+    std::fs::create_dir_all(dir.path().join("full")).unwrap();
+    std::fs::create_dir_all(dir.path().join("empty")).unwrap();
+    std::fs::write(dir.path().join("full/file"), b"file").unwrap();
+
+    let paths = get_recursive_paths(dir.path(), SymlinkPolicy::Skip);
+    assert_eq!(paths.len(), 4);
+    assert_eq!(paths.last().unwrap(), dir.path());
+    assert!(paths.contains(&dir.path().join("empty")));
+
+    let position = |p: &str| paths.iter().position(|path| path == &dir.path().join(p)).unwrap();
+    assert!(position("full/file") < position("full"));
+}
+
+#[cfg(unix)]
+#[test]
+fn symlink_paths_test() {
+    let dir = tempfile::tempdir().unwrap();
+    std::fs::create_dir_all(dir.path().join("dir")).unwrap();
+    std::fs::write(dir.path().join("dir/file"), b"file").unwrap();
+    std::os::unix::fs::symlink("file", dir.path().join("dir/link")).unwrap();
+    // points back at its own parent
+    std::os::unix::fs::symlink("..", dir.path().join("dir/cycle")).unwrap();
+
+    let skip = get_recursive_paths(dir.path(), SymlinkPolicy::Skip);
+    assert_eq!(skip.len(), 3);
+
+    let copy = get_recursive_paths(dir.path(), SymlinkPolicy::CopyLink);
+    assert_eq!(copy.len(), 5);
+    assert!(copy.contains(&dir.path().join("dir/cycle")));
+
+    let follow = get_recursive_paths(dir.path(), SymlinkPolicy::Follow);
+    assert_eq!(follow.len(), 4);
+    assert!(follow.contains(&dir.path().join("dir/link")));
+}
+
+#[test]
+fn escapes_tree_test() {
+    use std::path::Path;
+
+    assert!(!escapes_tree(Path::new("a/b/link"), Path::new("../c")));
+    assert!(!escapes_tree(Path::new("a/link"), Path::new("./b/../c")));
+    assert!(escapes_tree(Path::new("a/link"), Path::new("../../c")));
+    assert!(escapes_tree(Path::new("link"), Path::new("/etc/passwd")));
+    assert!(escapes_tree(Path::new("a/link"), Path::new("b/../../../c")));
 }
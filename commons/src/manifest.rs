@@ -0,0 +1,165 @@
+use std::{collections::HashMap, fs, io::Write, path::{Path, PathBuf}};
+use super::{error::Error, SymlinkPolicy};
+
+/// Name of the manifest the receiver writes at the root of the destination
+pub const MANIFEST: &str = ".atilink-manifest";
+
+/// What a manifest tells of every file
+#[derive(Debug, Clone, Copy, PartialEq, serde::Serialize, serde::Deserialize)]
+pub enum Detail {
+    Sizes,
+    /// sizes along with the md5 of the content, reads every file once more on the sender
+    Digests,
+}
+
+impl std::str::FromStr for Detail {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "sizes" => Ok(Self::Sizes),
+            "digests" => Ok(Self::Digests),
+            _ => Err(Error::InvalidArgument(format!("invalid manifest {s}"))),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, serde::Serialize, serde::Deserialize)]
+pub enum Kind {
+    File,
+    Directory,
+    Symlink,
+}
+
+/// One entry of the transferred tree
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct Entry {
+    pub rel_path: PathBuf,
+    pub kind: Kind,
+    /// `None` for anything but regular files
+    pub size: Option<u64>,
+    pub digest: Option<[u8; 16]>,
+}
+
+/// Every entry the sender is about to send, exchanged before any data
+#[derive(Debug, Default, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct Manifest {
+    pub entries: Vec<Entry>,
+}
+
+/// How the destination differs from the manifest once the transfer is done
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct Report {
+    /// listed but not at the destination
+    pub missing: Vec<PathBuf>,
+    /// at the destination with another kind or size than listed
+    pub mismatched: Vec<PathBuf>,
+    /// at the destination but not listed, only looked for when a whole tree is sent
+    pub extra: Vec<PathBuf>,
+}
+
+impl Manifest {
+    /// lists `entries`, as (source, relative path), the way they are sent under `symlinks`
+    pub fn read(entries: &[(PathBuf, PathBuf)], symlinks: SymlinkPolicy, detail: Detail) -> Result<Self, Error> {
+        let entries = entries.iter().map(|(source, rel_path)| {
+            let metadata = match symlinks {
+                SymlinkPolicy::CopyLink => fs::symlink_metadata(source)?,
+                _ => fs::metadata(source)?,
+            };
+            let kind = match metadata.file_type() {
+                t if t.is_symlink() => Kind::Symlink,
+                t if t.is_dir() => Kind::Directory,
+                _ => Kind::File,
+            };
+            let digest = match (kind, detail) {
+                (Kind::File, Detail::Digests) => Some(crate::quick_check::digest(source)?),
+                _ => None,
+            };
+
+            Ok(Entry { rel_path: rel_path.clone(), kind, size: (kind == Kind::File).then_some(metadata.len()), digest })
+        }).collect::<Result<Vec<Entry>, Error>>()?;
+
+        Ok(Self { entries })
+    }
+
+    /// size of every listed file
+    pub fn bytes(&self) -> u64 {
+        self.entries.iter().filter_map(|e| e.size).sum()
+    }
+
+    pub fn files(&self) -> usize {
+        self.entries.iter().filter(|e| e.kind == Kind::File).count()
+    }
+
+    /// writes one `kind size md5 path` line per entry, `-` standing for what isn't known
+    pub fn write(&self, path: &Path) -> Result<(), Error> {
+        let mut file = std::io::BufWriter::new(fs::File::create(path)?);
+        writeln!(file, "# kind size md5 path")?;
+        for entry in &self.entries {
+            let kind = match entry.kind {
+                Kind::File => "file",
+                Kind::Directory => "dir",
+                Kind::Symlink => "link",
+            };
+            let size = entry.size.map_or("-".to_string(), |s| s.to_string());
+            let digest = entry.digest.map_or("-".to_string(), |d| d.iter().map(|b| format!("{b:02x}")).collect());
+            let rel_path = match entry.rel_path.as_os_str().is_empty() {
+                true => Path::new("."),
+                false => entry.rel_path.as_path(),
+            };
+            writeln!(file, "{kind} {size} {digest} {}", rel_path.display())?;
+        }
+        writeln!(file, "# {} files, {} bytes", self.files(), self.bytes())?;
+
+        Ok(file.into_inner().map_err(|e| e.into_error())?.sync_all()?)
+    }
+
+    /// compares `destination` to the manifest, entries written elsewhere are looked for at their new path in `renames`
+    pub fn compare(&self, destination: &Path, renames: &HashMap<PathBuf, PathBuf>) -> Report {
+        let mut report = Report::default();
+        let expected = self.entries.iter().map(|e| renames.get(&e.rel_path).unwrap_or(&e.rel_path)).collect::<Vec<&PathBuf>>();
+
+        for (entry, rel_path) in self.entries.iter().zip(&expected) {
+            let Ok(metadata) = fs::symlink_metadata(destination.join(rel_path)) else {
+                report.missing.push(rel_path.to_path_buf());
+                continue;
+            };
+            let matches = match entry.kind {
+                Kind::File => metadata.is_file() && Some(metadata.len()) == entry.size,
+                Kind::Directory => metadata.is_dir(),
+                Kind::Symlink => metadata.is_symlink(),
+            };
+            if !matches {
+                report.mismatched.push(rel_path.to_path_buf());
+            }
+        }
+
+        // a single file leaves its siblings alone, as mirroring does
+        if self.entries.iter().any(|e| e.rel_path.as_os_str().is_empty()) {
+            report.extra = crate::mirror::extraneous(destination, expected.into_iter());
+        }
+
+        report
+    }
+}
+
+#[test]
+fn manifest_test() {
+    let dir = tempfile::tempdir().unwrap();
+    let source = dir.path().join("source");
+    let sink = dir.path().join("sink");
+    fs::create_dir_all(source.join("nested")).unwrap();
+    fs::write(source.join("nested/file"), b"content").unwrap();
+    fs::create_dir_all(sink.join("nested")).unwrap();
+    fs::write(sink.join("nested/file"), b"other").unwrap();
+    fs::write(sink.join("extra"), b"extra").unwrap();
+
+    let entries = crate::relative_entries(&source, crate::get_recursive_paths(&source, SymlinkPolicy::CopyLink)).unwrap();
+    let manifest = Manifest::read(&entries, SymlinkPolicy::CopyLink, Detail::Digests).unwrap();
+    assert_eq!((manifest.files(), manifest.bytes()), (1, 7));
+    assert_eq!(manifest.entries.iter().find(|e| e.kind == Kind::File).unwrap().digest, Some(md5::compute(b"content").0));
+
+    manifest.write(&sink.join(MANIFEST)).unwrap();
+    let report = manifest.compare(&sink, &HashMap::new());
+    assert_eq!(report, Report { missing: vec![], mismatched: vec![PathBuf::from("nested/file")], extra: vec![PathBuf::from("extra")] });
+}
@@ -0,0 +1,68 @@
+use std::{collections::HashSet, fs, path::{Path, PathBuf}};
+use super::{error::Error, SymlinkPolicy};
+
+/// Makes the destination an exact copy of the source tree, removing what the source doesn't have
+#[derive(Debug, Default, Clone, Copy, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct Mirror {
+    /// refuse to delete anything past this many entries
+    pub max_deletions: Option<usize>,
+}
+
+impl Mirror {
+    pub fn with_max_deletions(self, max_deletions: Option<usize>) -> Self {
+        Self { max_deletions }
+    }
+
+    /// fails when `deletions` is over the maximum
+    pub fn check(&self, deletions: usize) -> Result<(), Error> {
+        match self.max_deletions {
+            Some(max) if deletions > max => Err(Error::InvalidRequest(format!("refusing to delete {deletions} entries, over the maximum of {max}"))),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// entries under `destination` that are not in `offered`, relative to it, the manifest aside.
+/// The content of a directory comes before the directory, links are not followed.
+pub fn extraneous<'a>(destination: &Path, offered: impl Iterator<Item = &'a PathBuf>) -> Vec<PathBuf> {
+    if !destination.is_dir() {
+        return Vec::new();
+    }
+
+    let offered = offered.collect::<HashSet<&PathBuf>>();
+    super::get_recursive_paths(destination, SymlinkPolicy::CopyLink).into_iter()
+        .filter_map(|path| path.strip_prefix(destination).ok().map(Path::to_path_buf))
+        .filter(|rel| !rel.as_os_str().is_empty() && !offered.contains(rel) && rel != Path::new(crate::manifest::MANIFEST))
+        .collect()
+}
+
+/// removes `deletions`, as returned by [`extraneous`], from `destination`
+pub fn delete(destination: &Path, deletions: &[PathBuf]) -> Result<(), Error> {
+    for rel in deletions {
+        let path = destination.join(rel);
+        tracing::info!("deleting {}", path.display());
+        match fs::symlink_metadata(&path)?.is_dir() {
+            true => fs::remove_dir(&path)?,
+            false => fs::remove_file(&path)?,
+        }
+    }
+
+    Ok(())
+}
+
+#[test]
+fn mirror_test() {
+    let dir = tempfile::tempdir().unwrap();
+    fs::create_dir_all(dir.path().join("kept/gone")).unwrap();
+    fs::write(dir.path().join("kept/file"), b"file").unwrap();
+    fs::write(dir.path().join("kept/gone/file"), b"file").unwrap();
+
+    let offered = [PathBuf::from(""), PathBuf::from("kept"), PathBuf::from("kept/file")];
+    let deletions = extraneous(dir.path(), offered.iter());
+    assert_eq!(deletions, vec![PathBuf::from("kept/gone/file"), PathBuf::from("kept/gone")]);
+
+    assert!(Mirror::default().with_max_deletions(Some(1)).check(deletions.len()).is_err());
+    delete(dir.path(), &deletions).unwrap();
+    assert!(!dir.path().join("kept/gone").exists());
+    assert!(dir.path().join("kept/file").exists());
+}
@@ -0,0 +1,176 @@
+use std::{fs, io::Read, path::{Path, PathBuf}, time::SystemTime};
+use super::{error::Error, Conflict};
+
+/// How the receiver decides it already has a file, so the sender can skip it
+#[derive(Debug, Clone, Copy, PartialEq, serde::Serialize, serde::Deserialize)]
+pub enum QuickCheck {
+    /// same size and modification time, only useful with `--preserve=times`
+    SizeTime,
+    /// same size and content digest, reads every file on both ends
+    Digest,
+}
+
+impl std::str::FromStr for QuickCheck {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "size-time" => Ok(Self::SizeTime),
+            "digest" => Ok(Self::Digest),
+            _ => Err(Error::InvalidArgument(format!("invalid quick check {s}"))),
+        }
+    }
+}
+
+/// An entry the sender is about to send, as offered to the receiver
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct Offer {
+    pub rel_path: PathBuf,
+    /// `None` for anything but regular files, those are always sent
+    pub size: Option<u64>,
+    pub modified: Option<SystemTime>,
+    /// md5 of the content, filled in for [`QuickCheck::Digest`]
+    pub digest: Option<[u8; 16]>,
+}
+
+/// What the receiver does with an offered entry
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub enum Action {
+    /// nothing at the destination yet
+    Create,
+    /// replaces what is at the destination
+    Overwrite,
+    /// already at the destination or kept by the [`Conflict`] policy, not sent
+    Skip,
+    /// written under another path relative to the destination, the existing file is kept
+    Rename { to: PathBuf },
+    /// the [`Conflict`] policy forbids touching the existing file, the transfer is aborted
+    Fail,
+}
+
+impl Offer {
+    /// describes the regular file at `source`, sent as `rel_path`
+    pub fn file(source: &Path, rel_path: &Path, metadata: &fs::Metadata, check: Option<QuickCheck>) -> Result<Self, Error> {
+        let digest = match check {
+            Some(QuickCheck::Digest) => Some(digest(source)?),
+            _ => None,
+        };
+
+        Ok(Self {
+            rel_path: rel_path.to_path_buf(),
+            size: Some(metadata.len()),
+            modified: metadata.modified().ok(),
+            digest,
+        })
+    }
+
+    /// describes an entry that is always sent
+    pub fn other(rel_path: &Path) -> Self {
+        Self {
+            rel_path: rel_path.to_path_buf(),
+            size: None,
+            modified: None,
+            digest: None,
+        }
+    }
+
+    /// what the receiver does with this entry, to be written at `path`.
+    /// Identical files are skipped, `conflict` decides for the other existing files.
+    pub fn action(&self, path: &Path, check: Option<QuickCheck>, conflict: Conflict) -> Action {
+        let Ok(existing) = fs::symlink_metadata(path) else {
+            return Action::Create;
+        };
+
+        if check.is_some_and(|check| self.matches(path, check)) {
+            return Action::Skip;
+        }
+
+        // directories are merged, links replaced
+        if self.size.is_none() || !existing.is_file() {
+            return Action::Overwrite;
+        }
+
+        match conflict {
+            Conflict::Overwrite => Action::Overwrite,
+            Conflict::SkipExisting => Action::Skip,
+            Conflict::NewerOnly => match self.modified.zip(existing.modified().ok()) {
+                Some((incoming, existing)) if incoming <= existing => Action::Skip,
+                _ => Action::Overwrite,
+            },
+            Conflict::RenameNew => Action::Rename { to: self.rel_path.clone() },
+            Conflict::Fail => Action::Fail,
+        }
+    }
+
+    /// whether `path` already holds this entry
+    pub fn matches(&self, path: &Path, check: QuickCheck) -> bool {
+        let Some(size) = self.size else {
+            return false;
+        };
+
+        match fs::symlink_metadata(path) {
+            Ok(metadata) if metadata.is_file() && metadata.len() == size => match check {
+                QuickCheck::SizeTime => self.modified.is_some() && metadata.modified().ok() == self.modified,
+                QuickCheck::Digest => self.digest.is_some() && digest(path).ok() == self.digest,
+            },
+            _ => false,
+        }
+    }
+}
+
+/// The reply of the receiver to the offers of the sender
+#[derive(Debug, Default, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct Answer {
+    /// one per offer
+    pub actions: Vec<Action>,
+    /// entries the receiver deletes once the transfer is done, see [`Mirror`](crate::mirror::Mirror)
+    pub deletions: Vec<PathBuf>,
+    /// why the receiver refuses the transfer
+    pub error: Option<String>,
+}
+
+/// first free name of `rel_path` under `destination` with a numbered suffix, like `file.txt.1`, not in `taken`
+pub fn rename(destination: &Path, rel_path: &Path, taken: &std::collections::HashSet<PathBuf>) -> PathBuf {
+    (1..).map(|n| {
+        let mut name = rel_path.as_os_str().to_owned();
+        name.push(format!(".{n}"));
+        PathBuf::from(name)
+    }).find(|candidate| !taken.contains(candidate) && fs::symlink_metadata(destination.join(candidate)).is_err()).unwrap()
+}
+
+/// md5 of the content of a file, read in [`CHUNK`](crate::CHUNK) sized pieces
+pub(crate) fn digest(path: &Path) -> Result<[u8; 16], Error> {
+    let mut file = fs::File::open(path)?;
+    let mut context = md5::Context::new();
+    let mut buffer = vec![0; crate::CHUNK];
+
+    loop {
+        match file.read(&mut buffer)? {
+            0 => return Ok(context.compute().0),
+            n => context.consume(&buffer[..n]),
+        }
+    }
+}
+
+#[test]
+fn quick_check_test() {
+    let dir = tempfile::tempdir().unwrap();
+    let source = dir.path().join("source");
+    let sink = dir.path().join("sink");
+    fs::write(&source, b"content").unwrap();
+    fs::write(&sink, b"CONTENT").unwrap();
+
+    let metadata = fs::metadata(&source).unwrap();
+    let offer = Offer::file(&source, Path::new("file"), &metadata, Some(QuickCheck::Digest)).unwrap();
+    assert!(offer.matches(&source, QuickCheck::Digest));
+    assert!(!offer.matches(&sink, QuickCheck::Digest));
+    assert!(!offer.matches(&dir.path().join("missing"), QuickCheck::Digest));
+
+    fs::File::options().write(true).open(&sink).unwrap().set_modified(metadata.modified().unwrap()).unwrap();
+    assert!(offer.matches(&sink, QuickCheck::SizeTime));
+    assert!(!Offer::other(Path::new("dir")).matches(dir.path(), QuickCheck::SizeTime));
+    assert_eq!(offer.action(&sink, Some(QuickCheck::Digest), Conflict::Overwrite), Action::Overwrite);
+    assert_eq!(offer.action(&sink, None, Conflict::SkipExisting), Action::Skip);
+    assert_eq!(offer.action(&dir.path().join("missing"), None, Conflict::Fail), Action::Create);
+    assert_eq!(rename(dir.path(), Path::new("sink"), &std::collections::HashSet::from([PathBuf::from("sink.1")])), PathBuf::from("sink.2"));
+}
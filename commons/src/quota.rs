@@ -0,0 +1,206 @@
+use std::{fs, io::Write, path::{Path, PathBuf}, str::FromStr, sync::{Arc, Mutex}};
+use super::{error::Error, SymlinkPolicy};
+
+/// Storage limits of a share, `None` leaves it unlimited
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct Quota {
+    pub bytes: Option<u64>,
+    /// regular files only, directories and links are free
+    pub files: Option<u64>,
+}
+
+/// Parses quotas like `10GB`, `10GB,5000` or `,5000`, the optional second part being the number of files
+impl FromStr for Quota {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (bytes, files) = s.split_once(',').unwrap_or((s, ""));
+        let bytes = Some(bytes.trim()).filter(|b| !b.is_empty()).map(crate::parse_size).transpose()?;
+        let files = Some(files.trim()).filter(|f| !f.is_empty()).map(|f| f.parse::<u64>().map_err(|_| Error::InvalidArgument(format!("invalid file count in quota {s}")))).transpose()?;
+
+        match (bytes, files) {
+            (None, None) => Err(Error::InvalidArgument(format!("empty quota {s}"))),
+            _ => Ok(Self { bytes, files }),
+        }
+    }
+}
+
+/// Bytes and regular files held in a share
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct Usage {
+    pub bytes: u64,
+    pub files: u64,
+}
+
+impl Usage {
+    /// counts the regular files under `root`
+    pub fn scan(root: &Path) -> Self {
+        crate::get_recursive_paths(root, SymlinkPolicy::CopyLink).iter()
+            .filter_map(|path| fs::symlink_metadata(path).ok().filter(fs::Metadata::is_file))
+            .fold(Self::default(), |usage, metadata| Self { bytes: usage.bytes + metadata.len(), files: usage.files + 1 })
+    }
+}
+
+/// Usage of a share along with its quota.
+/// Clones share the same usage, so every link receiving into the share counts against it together.
+#[derive(Debug, Clone)]
+pub struct Account {
+    root: PathBuf,
+    quota: Quota,
+    usage: Arc<Mutex<Usage>>,
+}
+
+impl Account {
+    pub fn new(root: &Path, quota: Quota, usage: Usage) -> Self {
+        Self { root: root.to_path_buf(), quota, usage: Arc::new(Mutex::new(usage)) }
+    }
+
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    pub fn usage(&self) -> Usage {
+        *self.usage.lock().unwrap()
+    }
+
+    /// fails when `bytes` more in `files` more files go over the quota
+    pub fn check(&self, bytes: u64, files: u64) -> Result<(), Error> {
+        let usage = self.usage();
+        exceeds(self.quota.bytes, usage.bytes.saturating_add(bytes), "bytes")?;
+        exceeds(self.quota.files, usage.files.saturating_add(files), "files")
+    }
+
+    /// fails when `bytes` in `files` files go over the quota on their own, whatever they replace
+    pub fn admits(&self, bytes: u64, files: u64) -> Result<(), Error> {
+        exceeds(self.quota.bytes, bytes, "bytes")?;
+        exceeds(self.quota.files, files, "files")
+    }
+
+    /// fails when a file of `old` bytes, `None` when there is none, can't be replaced by one of `new` bytes
+    pub fn fits(&self, old: Option<u64>, new: Option<u64>) -> Result<(), Error> {
+        let usage = self.usage();
+        check_replace(self.quota, &usage, old, new).map(|_| ())
+    }
+
+    /// accounts for a file of `old` bytes, `None` when there is none, replaced by one of `new` bytes, `None` when removed.
+    /// Nothing is accounted when it goes over the quota, shrinking is always allowed.
+    pub fn replace(&self, old: Option<u64>, new: Option<u64>) -> Result<(), Error> {
+        let mut usage = self.usage.lock().unwrap();
+        *usage = check_replace(self.quota, &usage, old, new)?;
+        Ok(())
+    }
+}
+
+/// usage once `old` is replaced by `new`, failing only when it grows over `quota`
+fn check_replace(quota: Quota, usage: &Usage, old: Option<u64>, new: Option<u64>) -> Result<Usage, Error> {
+    let (old_bytes, new_bytes) = (old.unwrap_or_default(), new.unwrap_or_default());
+    let bytes = usage.bytes.saturating_sub(old_bytes).saturating_add(new_bytes);
+    let files = usage.files.saturating_sub(old.is_some() as u64).saturating_add(new.is_some() as u64);
+
+    if new_bytes > old_bytes {
+        exceeds(quota.bytes, bytes, "bytes")?;
+    }
+    if new.is_some() && old.is_none() {
+        exceeds(quota.files, files, "files")?;
+    }
+
+    Ok(Usage { bytes, files })
+}
+
+fn exceeds(limit: Option<u64>, value: u64, unit: &str) -> Result<(), Error> {
+    match limit {
+        Some(limit) if value > limit => Err(Error::InvalidRequest(format!("quota exceeded, {value} {unit} over the limit of {limit}"))),
+        _ => Ok(()),
+    }
+}
+
+/// Accounts of every share, their usage persisted in a file of `bytes files path` lines
+#[derive(Debug)]
+pub struct Ledger {
+    path: PathBuf,
+    accounts: Vec<Account>,
+    /// connections save concurrently
+    saving: Mutex<()>,
+}
+
+impl Ledger {
+    /// loads the usage of `shares` from the file at `path`, shares missing from it are scanned
+    pub fn load(path: &Path, shares: &[(PathBuf, Quota)]) -> Result<Self, Error> {
+        let saved = match fs::read_to_string(path) {
+            Ok(content) => content,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => String::new(),
+            Err(e) => return Err(e.into()),
+        };
+        let saved = saved.lines().filter_map(|line| {
+            let mut parts = line.splitn(3, ' ');
+            let usage = Usage { bytes: parts.next()?.parse().ok()?, files: parts.next()?.parse().ok()? };
+            Some((PathBuf::from(parts.next()?), usage))
+        }).collect::<std::collections::HashMap<PathBuf, Usage>>();
+
+        let mut accounts = Vec::with_capacity(shares.len());
+        for (root, quota) in shares {
+            let root = fs::canonicalize(root).map_err(|e| Error::InvalidArgument(format!("invalid share {}. {e}", root.display())))?;
+            let usage = match saved.get(&root) {
+                Some(usage) => *usage,
+                None => {
+                    tracing::info!("scanning share {}", root.display());
+                    Usage::scan(&root)
+                },
+            };
+            tracing::info!("share {} holds {} bytes in {} files", root.display(), usage.bytes, usage.files);
+            accounts.push(Account::new(&root, *quota, usage));
+        }
+
+        let ledger = Self { path: path.to_path_buf(), accounts, saving: Mutex::new(()) };
+        ledger.save()?;
+        Ok(ledger)
+    }
+
+    /// account of the innermost share holding `destination`
+    pub fn account(&self, destination: &Path) -> Option<Account> {
+        let destination = crate::resolve(destination);
+        self.accounts.iter().filter(|a| destination.starts_with(&a.root)).max_by_key(|a| a.root.components().count()).cloned()
+    }
+
+    /// writes the usage of every share, swapping the file in at once
+    pub fn save(&self) -> Result<(), Error> {
+        let _saving = self.saving.lock().unwrap();
+        let mut temp = self.path.clone().into_os_string();
+        temp.push(".tmp");
+
+        let mut file = fs::File::create(&temp)?;
+        for account in &self.accounts {
+            let usage = account.usage();
+            writeln!(file, "{} {} {}", usage.bytes, usage.files, account.root.display())?;
+        }
+        file.sync_all()?;
+        Ok(fs::rename(temp, &self.path)?)
+    }
+}
+
+#[test]
+fn quota_test() {
+    let dir = tempfile::tempdir().unwrap();
+    let share = dir.path().join("share");
+    fs::create_dir_all(share.join("nested")).unwrap();
+    fs::write(share.join("nested/file"), b"content").unwrap();
+
+    let quota = "10,2".parse::<Quota>().unwrap();
+    assert_eq!(quota, Quota { bytes: Some(10), files: Some(2) });
+    let ledger = Ledger::load(&dir.path().join("usage"), &[(share.clone(), quota)]).unwrap();
+    let account = ledger.account(&share.join("nested/new/../other")).unwrap();
+    assert!(ledger.account(&share.join("../elsewhere")).is_none());
+    assert_eq!(account.usage(), Usage { bytes: 7, files: 1 });
+
+    assert!(account.check(4, 0).is_err());
+    account.admits(10, 2).unwrap();
+    assert!(account.admits(11, 0).is_err());
+    assert!(account.replace(None, Some(4)).is_err());
+    account.replace(Some(7), Some(10)).unwrap();
+    account.replace(None, Some(0)).unwrap();
+    assert!(account.fits(None, Some(0)).is_err());
+    ledger.save().unwrap();
+
+    let ledger = Ledger::load(&dir.path().join("usage"), &[(share, quota)]).unwrap();
+    assert_eq!(ledger.accounts[0].usage(), Usage { bytes: 10, files: 2 });
+}
@@ -0,0 +1,167 @@
+use std::fs;
+
+/// Layout of a sparse file sent along with its [`FileMetadata`](crate::FileMetadata).
+/// Only the data extents travel as chunks, in order, everything in between is a hole.
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct Extents {
+    /// apparent size of the file, holes included
+    pub size: u64,
+    /// (offset, length) of every data region
+    pub data: Vec<(u64, u64)>,
+}
+
+impl Extents {
+    /// maps out the data regions of `file` with `SEEK_DATA`/`SEEK_HOLE`.
+    /// Returns `None` for files without holes or when the filesystem can't tell.
+    #[cfg(target_os = "linux")]
+    pub fn read(file: &fs::File, metadata: &fs::Metadata) -> Option<Self> {
+        use std::os::{fd::AsRawFd, unix::fs::MetadataExt};
+
+        let size = metadata.len();
+        // fully allocated files are sent as they are
+        if metadata.blocks() * 512 >= size {
+            return None;
+        }
+
+        let fd = file.as_raw_fd();
+        let mut data = Vec::new();
+        let mut offset = 0;
+        while offset < size {
+            // SAFETY: `fd` stays open for the duration of the call, lseek has no other preconditions
+            let start = unsafe { libc::lseek(fd, offset as libc::off_t, libc::SEEK_DATA) };
+            if start < 0 {
+                match std::io::Error::last_os_error().raw_os_error() {
+                    // no data past `offset`, the file ends with a hole
+                    Some(libc::ENXIO) => break,
+                    _ => return None,
+                }
+            }
+
+            // SAFETY: as above
+            let end = unsafe { libc::lseek(fd, start, libc::SEEK_HOLE) };
+            if end < 0 {
+                return None;
+            }
+
+            data.push((start as u64, (end - start) as u64));
+            offset = end as u64;
+        }
+
+        // SAFETY: as above, the position is rewound for the reader
+        unsafe { libc::lseek(fd, 0, libc::SEEK_SET) };
+        Some(Self { size, data })
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    pub fn read(_file: &fs::File, _metadata: &fs::Metadata) -> Option<Self> {
+        None
+    }
+
+    /// bytes sent on the wire
+    pub fn data_len(&self) -> u64 {
+        self.data.iter().map(|(_, len)| len).sum()
+    }
+
+    pub fn placement(&self) -> Placement<'_> {
+        Placement { extents: self, index: 0, done: 0 }
+    }
+}
+
+/// reserves `len` bytes of `file` from `offset`, growing it when needed, so a full disk shows up before anything is written.
+/// Filesystems without `fallocate` only get their length set.
+#[cfg(target_os = "linux")]
+pub fn allocate(file: &fs::File, offset: u64, len: u64) -> std::io::Result<()> {
+    use std::os::fd::AsRawFd;
+
+    if len == 0 {
+        return Ok(());
+    }
+
+    // SAFETY: the fd stays open for the duration of the call, fallocate has no other preconditions
+    if unsafe { libc::fallocate(file.as_raw_fd(), 0, offset as libc::off_t, len as libc::off_t) } == 0 {
+        return Ok(());
+    }
+
+    match std::io::Error::last_os_error() {
+        e if e.raw_os_error() == Some(libc::EOPNOTSUPP) => grow(file, offset + len),
+        e => Err(e),
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn allocate(file: &fs::File, offset: u64, len: u64) -> std::io::Result<()> {
+    grow(file, offset + len)
+}
+
+fn grow(file: &fs::File, len: u64) -> std::io::Result<()> {
+    match file.metadata()?.len() < len {
+        true => file.set_len(len),
+        false => Ok(()),
+    }
+}
+
+/// Tracks where the incoming data of a sparse file lands
+pub struct Placement<'a> {
+    extents: &'a Extents,
+    /// extent being filled
+    index: usize,
+    /// bytes of it already written
+    done: u64,
+}
+
+impl Placement<'_> {
+    /// file offset of the next `available` incoming bytes and how many of them fit in the current extent.
+    /// `None` once every extent is filled.
+    pub fn next(&mut self, available: usize) -> Option<(u64, usize)> {
+        let (offset, len) = *self.extents.data.get(self.index)?;
+        let n = (len - self.done).min(available as u64) as usize;
+        let at = offset + self.done;
+
+        self.done += n as u64;
+        if self.done == len {
+            self.index += 1;
+            self.done = 0;
+        }
+
+        Some((at, n))
+    }
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn extents_test() {
+    use std::io::{Seek, SeekFrom, Write};
+
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("sparse");
+    let mut file = fs::File::create(&path).unwrap();
+    file.seek(SeekFrom::Start(16 << 20)).unwrap();
+    file.write_all(&[1; 4096]).unwrap();
+    file.set_len(64 << 20).unwrap();
+    drop(file);
+
+    let file = fs::File::open(&path).unwrap();
+    let extents = Extents::read(&file, &file.metadata().unwrap()).unwrap();
+    assert_eq!(extents.size, 64 << 20);
+    assert_eq!(extents.data, vec![(16 << 20, 4096)]);
+
+    let mut placement = extents.placement();
+    assert_eq!(placement.next(1000), Some((16 << 20, 1000)));
+    assert_eq!(placement.next(5000), Some(((16 << 20) + 1000, 3096)));
+    assert_eq!(placement.next(5000), None);
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn allocate_test() {
+    use std::os::unix::fs::MetadataExt;
+
+    let dir = tempfile::tempdir().unwrap();
+    let file = fs::File::create(dir.path().join("allocated")).unwrap();
+    allocate(&file, 1 << 20, 1 << 20).unwrap();
+
+    let metadata = file.metadata().unwrap();
+    assert_eq!(metadata.len(), 2 << 20);
+    // the hole before the reserved range stays a hole
+    assert!(metadata.blocks() * 512 < 2 << 20);
+}
@@ -0,0 +1,120 @@
+use std::{str::FromStr, sync::{Arc, Mutex}, time::{Duration, Instant}};
+use super::error::Error;
+
+/// Share of a second the bucket may hold, keeps the traffic smooth instead of bursty
+const BURST_SECS: f64 = 0.05;
+
+/// Bandwidth limit implemented as a token bucket.
+/// Clones share the same bucket, so a single [`Throttle`] handed to several links caps them together.
+#[derive(Debug, Clone)]
+pub struct Throttle(Arc<Mutex<TokenBucket>>);
+
+#[derive(Debug)]
+struct TokenBucket {
+    /// bytes per second
+    rate: f64,
+    tokens: f64,
+    last: Instant,
+}
+
+impl TokenBucket {
+    fn capacity(&self) -> f64 {
+        (self.rate * BURST_SECS).max(1.0)
+    }
+
+    fn refill(&mut self) {
+        let now = Instant::now();
+        let elapsed = now.duration_since(self.last).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.rate).min(self.capacity());
+        self.last = now;
+    }
+}
+
+impl Throttle {
+    /// creates a throttle allowing `rate` bytes per second
+    pub fn new(rate: u64) -> Self {
+        let rate = rate.max(1) as f64;
+        Self(Arc::new(Mutex::new(TokenBucket {
+            rate,
+            tokens: 0.0,
+            last: Instant::now(),
+        })))
+    }
+
+    /// bytes per second
+    pub fn rate(&self) -> u64 {
+        self.0.lock().unwrap().rate as u64
+    }
+
+    /// changes the rate of every link sharing this throttle
+    pub fn set_rate(&self, rate: u64) {
+        let mut bucket = self.0.lock().unwrap();
+        bucket.refill();
+        bucket.rate = rate.max(1) as f64;
+    }
+
+    /// largest slice a caller should acquire at once to keep the traffic smooth
+    pub fn burst(&self) -> usize {
+        self.0.lock().unwrap().capacity() as usize
+    }
+
+    /// blocks until `bytes` can be sent.
+    /// The bucket goes into debt and the caller sleeps it off, later callers wait behind it.
+    pub fn acquire(&self, bytes: usize) {
+        let wait = {
+            let mut bucket = self.0.lock().unwrap();
+            bucket.refill();
+            bucket.tokens -= bytes as f64;
+            match bucket.tokens < 0.0 {
+                true => Some(Duration::from_secs_f64(-bucket.tokens / bucket.rate)),
+                false => None,
+            }
+        };
+
+        if let Some(wait) = wait {
+            std::thread::sleep(wait);
+        }
+    }
+}
+
+/// Parses rates like `50MB/s`, `512KiB/s`, `1G` or plain bytes per second
+impl FromStr for Throttle {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let value = s.trim();
+        let value = value.strip_suffix("/s").unwrap_or(value);
+        let rate = crate::parse_size(value).map_err(|_| Error::InvalidArgument(format!("invalid rate {s}")))?;
+
+        match rate {
+            0 => Err(Error::InvalidArgument(format!("rate {s} should be more than 0"))),
+            rate => Ok(Self::new(rate)),
+        }
+    }
+}
+
+#[test]
+fn rate_test() {
+    assert_eq!("50MB/s".parse::<Throttle>().unwrap().rate(), 50_000_000);
+    assert_eq!("512KiB/s".parse::<Throttle>().unwrap().rate(), 512 * 1024);
+    assert_eq!("1.5G".parse::<Throttle>().unwrap().rate(), 1_500_000_000);
+    assert_eq!("100".parse::<Throttle>().unwrap().rate(), 100);
+    assert!("0MB/s".parse::<Throttle>().is_err());
+    assert!("fast".parse::<Throttle>().is_err());
+    assert!("10XB/s".parse::<Throttle>().is_err());
+
+    let throttle = "1MB/s".parse::<Throttle>().unwrap();
+    throttle.clone().set_rate(10);
+    assert_eq!(throttle.rate(), 10);
+}
+
+#[test]
+fn acquire_test() {
+    let throttle = Throttle::new(100_000);
+    let instant = Instant::now();
+    for _ in 0..10 {
+        throttle.acquire(2_000);
+    }
+    // 20kb at 100kb/s
+    assert!(instant.elapsed() >= Duration::from_millis(190));
+}
@@ -0,0 +1,245 @@
+use std::{cell::Cell, io::{self, Read, Write}, net::{Shutdown, TcpStream}, process::{Child, ChildStdin, ChildStdout}, sync::mpsc, time::Duration};
+
+/// A byte stream a [`Link`](crate::connection::Link) can run over.
+/// To introduce a new transport, your type should implement this trait.
+/// Transports without timeout support treat the timeout setters as no-ops.
+pub trait Transport: Read + Write + Send {
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()>;
+    fn set_write_timeout(&self, timeout: Option<Duration>) -> io::Result<()>;
+    /// closes both halves of the transport, the peer reads EOF
+    fn shutdown(&mut self) -> io::Result<()>;
+}
+
+impl Transport for TcpStream {
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        TcpStream::set_read_timeout(self, timeout)
+    }
+
+    fn set_write_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        TcpStream::set_write_timeout(self, timeout)
+    }
+
+    fn shutdown(&mut self) -> io::Result<()> {
+        TcpStream::shutdown(self, Shutdown::Both)
+    }
+}
+
+#[cfg(unix)]
+impl Transport for std::os::unix::net::UnixStream {
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        std::os::unix::net::UnixStream::set_read_timeout(self, timeout)
+    }
+
+    fn set_write_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        std::os::unix::net::UnixStream::set_write_timeout(self, timeout)
+    }
+
+    fn shutdown(&mut self) -> io::Result<()> {
+        std::os::unix::net::UnixStream::shutdown(self, Shutdown::Both)
+    }
+}
+
+impl Transport for Box<dyn Transport> {
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        (**self).set_read_timeout(timeout)
+    }
+
+    fn set_write_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        (**self).set_write_timeout(timeout)
+    }
+
+    fn shutdown(&mut self) -> io::Result<()> {
+        (**self).shutdown()
+    }
+}
+
+/// TLS over any other transport, for both client and server connections
+#[cfg(feature = "tls")]
+impl<C, S, T> Transport for rustls::StreamOwned<C, T>
+where
+    C: std::ops::DerefMut + std::ops::Deref<Target = rustls::ConnectionCommon<S>> + Send,
+    S: rustls::SideData,
+    T: Transport,
+{
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        self.sock.set_read_timeout(timeout)
+    }
+
+    fn set_write_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        self.sock.set_write_timeout(timeout)
+    }
+
+    fn shutdown(&mut self) -> io::Result<()> {
+        self.conn.send_close_notify();
+        self.flush()?;
+        self.sock.shutdown()
+    }
+}
+
+/// The stdin and stdout of a child process, e.g. a server spawned over ssh
+pub struct ChildStdio {
+    stdin: Option<ChildStdin>,
+    stdout: ChildStdout,
+}
+
+impl ChildStdio {
+    pub fn new(stdin: ChildStdin, stdout: ChildStdout) -> Self {
+        Self {
+            stdin: Some(stdin),
+            stdout,
+        }
+    }
+
+    /// takes the piped stdio of the child.
+    /// Returns `None` if the child was not spawned with [`Stdio::piped`](std::process::Stdio::piped) for both
+    pub fn from_child(child: &mut Child) -> Option<Self> {
+        Some(Self::new(child.stdin.take()?, child.stdout.take()?))
+    }
+}
+
+impl Read for ChildStdio {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.stdout.read(buf)
+    }
+}
+
+impl Write for ChildStdio {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self.stdin.as_mut() {
+            Some(stdin) => stdin.write(buf),
+            None => Err(io::ErrorKind::BrokenPipe.into()),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self.stdin.as_mut() {
+            Some(stdin) => stdin.flush(),
+            None => Ok(()),
+        }
+    }
+}
+
+impl Transport for ChildStdio {
+    fn set_read_timeout(&self, _timeout: Option<Duration>) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn set_write_timeout(&self, _timeout: Option<Duration>) -> io::Result<()> {
+        Ok(())
+    }
+
+    /// closes stdin of the child, stdout is drained by the child exiting
+    fn shutdown(&mut self) -> io::Result<()> {
+        self.stdin.take();
+        Ok(())
+    }
+}
+
+/// number of writes buffered by a [`Pipe`] before the writer blocks
+const PIPE_CAPACITY: usize = 64;
+
+/// One end of an in-memory duplex pipe created by [`pipe`]
+pub struct Pipe {
+    tx: Option<mpsc::SyncSender<Vec<u8>>>,
+    rx: mpsc::Receiver<Vec<u8>>,
+    pending: Vec<u8>,
+    position: usize,
+    read_timeout: Cell<Option<Duration>>,
+}
+
+/// creates a connected pair of in-memory transports.
+/// Bytes written to one end are read from the other.
+pub fn pipe() -> (Pipe, Pipe) {
+    let (left_tx, right_rx) = mpsc::sync_channel(PIPE_CAPACITY);
+    let (right_tx, left_rx) = mpsc::sync_channel(PIPE_CAPACITY);
+    (Pipe::new(left_tx, left_rx), Pipe::new(right_tx, right_rx))
+}
+
+impl Pipe {
+    fn new(tx: mpsc::SyncSender<Vec<u8>>, rx: mpsc::Receiver<Vec<u8>>) -> Self {
+        Self {
+            tx: Some(tx),
+            rx,
+            pending: Vec::new(),
+            position: 0,
+            read_timeout: Cell::new(None),
+        }
+    }
+}
+
+impl Read for Pipe {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.position == self.pending.len() {
+            let received = match self.read_timeout.get() {
+                None => self.rx.recv().map_err(|_| mpsc::RecvTimeoutError::Disconnected),
+                Some(timeout) => self.rx.recv_timeout(timeout),
+            };
+
+            match received {
+                Ok(bytes) => {
+                    self.pending = bytes;
+                    self.position = 0;
+                },
+                // the other end is gone
+                Err(mpsc::RecvTimeoutError::Disconnected) => return Ok(0),
+                Err(mpsc::RecvTimeoutError::Timeout) => return Err(io::ErrorKind::TimedOut.into()),
+            }
+        }
+
+        let len = buf.len().min(self.pending.len() - self.position);
+        buf[..len].copy_from_slice(&self.pending[self.position..self.position + len]);
+        self.position += len;
+        Ok(len)
+    }
+}
+
+impl Write for Pipe {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+
+        match self.tx.as_ref() {
+            Some(tx) => tx.send(buf.to_vec()).map(|_| buf.len()).map_err(|_| io::ErrorKind::BrokenPipe.into()),
+            None => Err(io::ErrorKind::BrokenPipe.into()),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Transport for Pipe {
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        self.read_timeout.set(timeout);
+        Ok(())
+    }
+
+    fn set_write_timeout(&self, _timeout: Option<Duration>) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn shutdown(&mut self) -> io::Result<()> {
+        self.tx.take();
+        Ok(())
+    }
+}
+
+#[test]
+fn pipe_test() {
+    let (mut left, mut right) = pipe();
+    left.write_all(b"hello").unwrap();
+    left.shutdown().unwrap();
+
+    let mut buffer = Vec::new();
+    right.read_to_end(&mut buffer).unwrap();
+    assert_eq!(buffer, b"hello");
+
+    drop(left);
+    assert_eq!(right.write(b"closed").unwrap_err().kind(), io::ErrorKind::BrokenPipe);
+
+    let (mut left, _right) = pipe();
+    left.set_read_timeout(Some(Duration::from_millis(10))).unwrap();
+    assert_eq!(left.read(&mut [0; 4]).unwrap_err().kind(), io::ErrorKind::TimedOut);
+}
@@ -0,0 +1,110 @@
+use std::{collections::BTreeMap, fs, io, path::Path};
+
+/// Extended attributes of a file by name, POSIX ACLs and SELinux labels included
+pub type Xattrs = BTreeMap<String, Vec<u8>>;
+
+/// reads every extended attribute of `path`, without following links
+#[cfg(target_os = "linux")]
+pub fn read(path: &Path) -> io::Result<Xattrs> {
+    use std::{ffi::CString, os::unix::ffi::OsStrExt};
+
+    let path = CString::new(path.as_os_str().as_bytes())?;
+    let mut xattrs = Xattrs::new();
+
+    // SAFETY: `path` is nul terminated, a null buffer of size 0 only queries the size
+    let size = unsafe { libc::llistxattr(path.as_ptr(), std::ptr::null_mut(), 0) };
+    if size < 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    let mut names = vec![0u8; size as usize];
+    // SAFETY: `names` is valid for `names.len()` bytes
+    let size = unsafe { libc::llistxattr(path.as_ptr(), names.as_mut_ptr().cast(), names.len()) };
+    if size < 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    for name in names[..size as usize].split(|b| *b == 0).filter(|n| !n.is_empty()) {
+        let Ok(key) = std::str::from_utf8(name) else {
+            tracing::warn!("skipping non utf-8 extended attribute {}", String::from_utf8_lossy(name));
+            continue;
+        };
+        let name = CString::new(name)?;
+
+        // SAFETY: as above
+        let size = unsafe { libc::lgetxattr(path.as_ptr(), name.as_ptr(), std::ptr::null_mut(), 0) };
+        if size < 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        let mut value = vec![0u8; size as usize];
+        // SAFETY: `value` is valid for `value.len()` bytes
+        let size = unsafe { libc::lgetxattr(path.as_ptr(), name.as_ptr(), value.as_mut_ptr().cast(), value.len()) };
+        if size < 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        value.truncate(size as usize);
+        xattrs.insert(key.to_string(), value);
+    }
+
+    Ok(xattrs)
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn read(_path: &Path) -> io::Result<Xattrs> {
+    Ok(Xattrs::new())
+}
+
+/// sets every attribute it can on `file`, the ones that failed are returned along with the reason
+#[cfg(target_os = "linux")]
+pub fn apply(file: &fs::File, xattrs: &Xattrs) -> Vec<(String, io::Error)> {
+    use std::os::fd::AsRawFd;
+
+    let mut failed = Vec::new();
+    for (key, value) in xattrs {
+        let name = match std::ffi::CString::new(key.as_str()) {
+            Ok(name) => name,
+            Err(e) => {
+                failed.push((key.clone(), e.into()));
+                continue;
+            },
+        };
+
+        // SAFETY: the fd stays open for the duration of the call, `value` is valid for `value.len()` bytes
+        if unsafe { libc::fsetxattr(file.as_raw_fd(), name.as_ptr(), value.as_ptr().cast(), value.len(), 0) } < 0 {
+            failed.push((key.clone(), io::Error::last_os_error()));
+        }
+    }
+
+    failed
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn apply(_file: &fs::File, xattrs: &Xattrs) -> Vec<(String, io::Error)> {
+    xattrs.keys().map(|key| (key.clone(), io::ErrorKind::Unsupported.into())).collect()
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn xattrs_test() {
+    let dir = tempfile::tempdir().unwrap();
+    let source = dir.path().join("source");
+    let sink = dir.path().join("sink");
+    fs::write(&source, b"source").unwrap();
+    fs::write(&sink, b"sink").unwrap();
+
+    // not every filesystem takes user attributes, tmpfs only does on recent kernels
+    let mut xattrs = Xattrs::from([("user.atilink".to_string(), b"value".to_vec())]);
+    if !apply(&fs::File::open(&source).unwrap(), &xattrs).is_empty() {
+        return;
+    }
+
+    assert_eq!(read(&source).unwrap().get("user.atilink").unwrap(), b"value");
+
+    xattrs.insert("bogus.name".to_string(), b"value".to_vec());
+    let failed = apply(&fs::File::open(&sink).unwrap(), &xattrs);
+    assert_eq!(failed.len(), 1);
+    assert_eq!(failed[0].0, "bogus.name");
+    assert_eq!(read(&sink).unwrap().get("user.atilink").unwrap(), b"value");
+}
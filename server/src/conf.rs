@@ -1,5 +1,5 @@
-use std::net::{IpAddr, Ipv6Addr, SocketAddr};
-use commons::error::Error;
+use std::{net::{IpAddr, Ipv6Addr, SocketAddr}, path::{Path, PathBuf}};
+use commons::{error::Error, throttle::Throttle};
 
 /// method to create configuration based on the arguments passed
 pub fn fetch_conf() -> Result<Conf, Error> {
@@ -12,6 +12,40 @@ pub fn fetch_conf() -> Result<Conf, Error> {
                 None => return Err(Error::invalid_argument("no port value supplied")),
                 Some(v) => conf.socket = v.parse::<SocketAddr>()?,
             },
+            "-u" | "--unix" => match args.next() {
+                None => return Err(Error::invalid_argument("no unix socket path supplied")),
+                Some(v) => conf.unix = Some(PathBuf::from(v)),
+            },
+            "-l" | "--limit" => match args.next() {
+                None => return Err(Error::invalid_argument("no limit value supplied")),
+                Some(v) => conf.write_limit = Some(v.parse()?),
+            },
+            "-c" | "--conf" => match args.next() {
+                None => return Err(Error::invalid_argument("no configuration file supplied")),
+                Some(v) => {
+                    let limits = Limits::load(Path::new(&v))?;
+                    conf.write_limit = limits.write.or(conf.write_limit);
+                    conf.read_limit = limits.read.or(conf.read_limit);
+                    conf.conf_file = Some(PathBuf::from(v));
+                },
+            },
+            "--read-limit" => match args.next() {
+                None => return Err(Error::invalid_argument("no read limit value supplied")),
+                Some(v) => conf.read_limit = Some(v.parse()?),
+            },
+            "--reserve" => match args.next() {
+                None => return Err(Error::invalid_argument("no reserve value supplied")),
+                Some(v) => conf.reserve = commons::parse_size(&v)?,
+            },
+            "--quota" => match args.next().as_deref().and_then(|v| v.rsplit_once('=')) {
+                None => return Err(Error::invalid_argument("quota should be given as <share>=<bytes>[,<files>]")),
+                Some((share, quota)) => conf.quotas.push((PathBuf::from(share), quota.parse()?)),
+            },
+            "--usage-file" => match args.next() {
+                None => return Err(Error::invalid_argument("no usage file path supplied")),
+                Some(v) => conf.usage_file = PathBuf::from(v),
+            },
+            "--allow-mirror" => conf.allow_mirror = true,
             "-d" | "--debug" => conf.debug = true,
             _ => return Err(Error::InvalidArgument(format!("invalid property {s}"))), 
         }
@@ -21,20 +55,67 @@ pub fn fetch_conf() -> Result<Conf, Error> {
 
 }
 
+#[derive(Clone)]
 pub struct Conf {
     pub socket: SocketAddr,
+    /// unix domain socket listened to alongside [`socket`](Self::socket)
+    pub unix: Option<PathBuf>,
     pub ttl: std::time::Duration,
     pub read_timeout: Option<std::time::Duration>,
     pub debug: bool,
+    /// global cap on bytes sent, shared by every connection
+    pub write_limit: Option<commons::throttle::Throttle>,
+    /// global cap on bytes received, shared by every connection
+    pub read_limit: Option<commons::throttle::Throttle>,
+    /// file the limits are read from, and re-read when it changes
+    pub conf_file: Option<PathBuf>,
+    /// bytes left free on the destination filesystem, uploads that would eat into them are refused
+    pub reserve: u64,
+    /// directories uploads are counted against, with their limits
+    pub quotas: Vec<(PathBuf, commons::quota::Quota)>,
+    /// where the usage of every share is kept across restarts
+    pub usage_file: PathBuf,
+    /// uploads may delete what their destination holds and the source doesn't
+    pub allow_mirror: bool,
 }
 
 impl Default for Conf {
     fn default() -> Self {
         Self {
             socket: SocketAddr::new(IpAddr::V6(Ipv6Addr::LOCALHOST), 9099),
+            unix: None,
             ttl: std::time::Duration::from_secs(100),
             read_timeout: Some(std::time::Duration::from_secs(10)),
             debug: false,
+            write_limit: None,
+            read_limit: None,
+            conf_file: None,
+            reserve: 0,
+            quotas: Vec::new(),
+            usage_file: PathBuf::from("atilink-usage"),
+            allow_mirror: false,
         }
     }
 }
+
+/// Bandwidth limits of the `[settings]` table of a configuration file
+#[derive(Debug, Default)]
+pub struct Limits {
+    pub write: Option<Throttle>,
+    pub read: Option<Throttle>,
+}
+
+impl Limits {
+    /// reads `limit` and `read-limit` from the file at `path`
+    pub fn load(path: &Path) -> Result<Self, Error> {
+        let content = std::fs::read_to_string(path).map_err(|e| Error::InvalidArgument(format!("cannot read configuration {}. {e}", path.display())))?;
+        let table = content.parse::<toml::Table>().map_err(|e| Error::InvalidArgument(format!("invalid configuration {}. {e}", path.display())))?;
+        let settings = table.get("settings");
+        let limit = |key: &str| settings.and_then(|s| s.get(key)).and_then(toml::Value::as_str).map(str::parse::<Throttle>).transpose();
+
+        Ok(Self {
+            write: limit("limit")?,
+            read: limit("read-limit")?,
+        })
+    }
+}
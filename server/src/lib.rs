@@ -1,7 +1,8 @@
 mod conf;
 
-use std::net::TcpListener;
-use conf::fetch_conf;
+use std::{net::TcpListener, path::PathBuf, sync::Arc, time::Duration};
+use commons::{quota::Ledger, throttle::Throttle, transport::Transport};
+use conf::{fetch_conf, Conf, Limits};
 
 pub fn init() -> Result<(), Box<dyn std::error::Error>> {
     let conf = fetch_conf()?;
@@ -15,53 +16,136 @@ pub fn init() -> Result<(), Box<dyn std::error::Error>> {
 
     tracing::info!("starting client...\nlistening on address {}", conf.socket);
 
+    let ledger = match conf.quotas.is_empty() {
+        true => None,
+        false => Some(Arc::new(Ledger::load(&conf.usage_file, &conf.quotas).inspect_err(|e| tracing::error!("cannot load quotas. {e}"))?)),
+    };
+
+    if let Some(path) = conf.conf_file.clone() {
+        let (write_limit, read_limit) = (conf.write_limit.clone(), conf.read_limit.clone());
+        std::thread::spawn(move || watch_limits(path, write_limit, read_limit));
+    }
+
     let listener = TcpListener::bind(conf.socket).inspect_err(|e| tracing::error!("cannot connet to socket {0}. {1}", conf.socket, e))?;
     listener.set_ttl(conf.ttl.as_secs() as u32).inspect_err(|e| tracing::error!("error setting TTL {e}"))?;
 
-    for stream in listener.incoming() {
+    #[cfg(unix)]
+    if let Some(path) = conf.unix.as_ref() {
+        let listener = bind_unix(path)?;
+        tracing::info!("listening on unix socket {}", path.display());
+        let (conf, ledger) = (conf.clone(), ledger.clone());
+        std::thread::spawn(move || serve(listener.incoming(), &conf, ledger.as_deref()));
+    }
+
+    serve(listener.incoming(), &conf, ledger.as_deref());
+
+    Ok(())
+}
+
+/// re-reads the limits of the configuration file at `path` once it changes and adjusts the running throttles, every connection included
+fn watch_limits(path: PathBuf, write_limit: Option<Throttle>, read_limit: Option<Throttle>) {
+    let modified = |path: &PathBuf| std::fs::metadata(path).and_then(|m| m.modified()).ok();
+    let mut last = modified(&path);
+    loop {
+        std::thread::sleep(Duration::from_secs(1));
+        let current = modified(&path);
+        if current == last {
+            continue;
+        }
+        last = current;
+
+        match Limits::load(&path) {
+            Ok(limits) => {
+                adjust("send", write_limit.as_ref(), limits.write);
+                adjust("receive", read_limit.as_ref(), limits.read);
+            },
+            Err(e) => tracing::error!("keeping the current limits. {e}"),
+        }
+    }
+}
+
+/// sets the rate of `throttle` to the one of `limit`, a limit can't be added or removed while running
+fn adjust(direction: &str, throttle: Option<&Throttle>, limit: Option<Throttle>) {
+    match (throttle, limit) {
+        (Some(throttle), Some(limit)) if throttle.rate() != limit.rate() => {
+            throttle.set_rate(limit.rate());
+            tracing::info!("{direction} limit set to {} bytes/s", limit.rate());
+        },
+        (Some(_), Some(_)) | (None, None) => {},
+        _ => tracing::warn!("the {direction} limit can only be added or removed by restarting the server"),
+    }
+}
+
+/// binds a unix domain socket, replacing a stale socket file left by a previous run
+#[cfg(unix)]
+fn bind_unix(path: &std::path::Path) -> Result<std::os::unix::net::UnixListener, commons::error::Error> {
+    use std::os::unix::fs::FileTypeExt;
+
+    if let Ok(metadata) = std::fs::symlink_metadata(path) {
+        if !metadata.file_type().is_socket() {
+            return Err(commons::error::Error::InvalidArgument(format!("{} exists and is not a socket", path.display())));
+        }
+        std::fs::remove_file(path)?;
+    }
+
+    Ok(std::os::unix::net::UnixListener::bind(path).inspect_err(|e| tracing::error!("cannot bind unix socket {0}. {1}", path.display(), e))?)
+}
+
+/// accepts connections one after the other and serves them
+fn serve<T: Transport>(incoming: impl Iterator<Item = std::io::Result<T>>, conf: &Conf, ledger: Option<&Ledger>) {
+    for stream in incoming {
         // don't terminate if a stream connection fails
         match stream {
             Ok(s) => {
-                s.set_read_timeout(conf.read_timeout).inspect_err(|e| tracing::error!("error setting read timeout {e}"))?;
-                let link = commons::connection::Link::new(s);
-                let _ = listen(link);
+                if let Err(e) = s.set_read_timeout(conf.read_timeout) {
+                    tracing::error!("error setting read timeout {e}");
+                    continue;
+                }
+                let link = commons::connection::Link::new(s).with_write_limit(conf.write_limit.clone()).with_read_limit(conf.read_limit.clone()).with_confined_links(true);
+                let _ = listen(link, conf, ledger);
             },
             Err(e) => tracing::error!("connection failed connecting to address: {e}"),
         }
     }
-
-    Ok(())
 }
 
 /// use the created [`Link`](commons::connection::Link) to listen to the stream
-fn listen(mut link: commons::connection::Link) -> Result<(), commons::error::Error> {
+fn listen<T: Transport>(mut link: commons::connection::Link<T>, conf: &Conf, ledger: Option<&Ledger>) -> Result<(), commons::error::Error> {
     match link.read_role()? {
         commons::Role::Source => {
             let download_metadata = link.read_download_metadata()?;
-            let path = download_metadata.destination;
+            // the requested path itself is always followed
+            let name = download_metadata.destination.file_name().map(std::path::PathBuf::from);
+            let path = std::fs::canonicalize(&download_metadata.destination).unwrap_or(download_metadata.destination);
 
             if path.is_dir() {
                 tracing::info!("path is a directory");
-                let all_files = commons::get_recursive_paths(&path);
-                let len = all_files.len();
-                link.write_ok_result(len)?;
-
-                for file in all_files {
-                    match file.strip_prefix(&path) {
-                        Ok(p) => link.write_to_stream(&file, &std::path::PathBuf::from(p))?,
-                        Err(e) => return Err(commons::error::Error::InvalidRequest(format!("{} is not a relative path of {}, {e}", file.to_str().unwrap(), path.to_str().unwrap()))),
-                    }
+                let all_files = commons::relative_entries(&path, commons::get_recursive_paths(&path, download_metadata.symlinks))?;
+                link.write_ok_result(all_files.len())?;
+
+                let entries = link.negotiate(all_files)?;
+                let len = entries.len();
+                for (file, rel) in entries {
+                    link.write_to_stream(&file, &rel)?;
                 }
                 tracing::info!("{} files uploaded", len);
-                
+                if let Some(summary) = link.read_summary()? {
+                    log_summary(&summary);
+                }
             }
             else if path.is_file() {
                 tracing::info!("path is a file");
                 link.write_ok_result(1)?;
 
-                link.write_to_stream(&path, &std::path::PathBuf::from(path.file_name().unwrap()))?;
+                let entries = link.negotiate(vec![(path, name.unwrap_or_default())])?;
+                for (file, rel) in &entries {
+                    link.write_to_stream(file, rel)?;
+                }
 
-                tracing::info!("1 file uploaded");
+                tracing::info!("{} file uploaded", entries.len());
+                if let Some(summary) = link.read_summary()? {
+                    log_summary(&summary);
+                }
             }
             else {
                 tracing::error!("invalid file path");
@@ -72,13 +156,94 @@ fn listen(mut link: commons::connection::Link) -> Result<(), commons::error::Err
             let metadata = link.read_upload_metadata()?;
             tracing::debug!("received upload metadata: {:?}", metadata);
 
-            for _ in 0..metadata.count {
-                link.read_from_stream(&metadata.destination, &metadata.eof_marker)?;
+            let account = ledger.and_then(|ledger| ledger.account(&metadata.destination));
+            let accepted = check_mirror(&metadata, conf.allow_mirror).and_then(|_| check_space(&metadata, conf.reserve)).and_then(|_| account.as_ref().map_or(Ok(()), |a| check_quota(a, &metadata)));
+            match accepted {
+                // nothing is written on a dry run, why the upload would be refused goes in the plan instead
+                Err(e) if metadata.dry_run => {
+                    tracing::warn!("dry run to {} would be refused. {e}", metadata.destination.display());
+                    link = link.with_refusal(Some(e.to_string()));
+                },
+                Err(e) => {
+                    tracing::error!("refusing upload to {}. {e}", metadata.destination.display());
+                    return link.write_err_result(e.to_string());
+                },
+                Ok(()) => {},
             }
+            link.write_ok_result(metadata.count as usize)?;
 
-            tracing::info!("{} files received", metadata.count);
+            link = link.with_account(account.clone());
+            let received = receive(&mut link, &metadata);
+            // nothing is written on a dry run, the usage file included
+            if let Some((ledger, account)) = ledger.zip(account).filter(|_| !metadata.dry_run) {
+                let usage = account.usage();
+                tracing::info!("share {} holds {} bytes in {} files", account.root().display(), usage.bytes, usage.files);
+                if let Err(e) = ledger.save() {
+                    tracing::error!("unable to save the usage of shares. {e}");
+                }
+            }
+            received?;
         },
     };
 
     Ok(())
 }
+
+/// receives every entry of an accepted upload
+fn receive<T: Transport>(link: &mut commons::connection::Link<T>, metadata: &commons::UploadMetadata) -> Result<(), commons::error::Error> {
+    let count = link.answer(&metadata.destination, metadata.count as usize)?;
+    for _ in 0..count {
+        link.read_from_stream(&metadata.destination, &metadata.eof_marker)?;
+    }
+
+    link.sync()?;
+    tracing::info!("{} files received, {:?} spent syncing", count, link.sync_time());
+
+    if let Err(e) = link.mirror(&metadata.destination) {
+        tracing::error!("unable to mirror {}. {e}", metadata.destination.display());
+    }
+
+    if let Some(report) = link.check_manifest(&metadata.destination)? {
+        report.missing.iter().for_each(|path| tracing::warn!("missing {}", path.display()));
+        report.mismatched.iter().for_each(|path| tracing::warn!("mismatched {}", path.display()));
+        report.extra.iter().for_each(|path| tracing::warn!("extra {}", path.display()));
+        tracing::info!("manifest checked, {} missing, {} mismatched, {} extra", report.missing.len(), report.mismatched.len(), report.extra.len());
+    }
+
+    log_summary(&link.write_summary()?);
+    Ok(())
+}
+
+/// logs the entries the receiver didn't write as sent
+fn log_summary(summary: &commons::ack::Summary) {
+    for ack in &summary.issues {
+        tracing::warn!("{} {}", ack.rel_path.display(), ack.status);
+    }
+    if let Some(reason) = &summary.mirror_refused {
+        tracing::warn!("nothing deleted, {reason}");
+    }
+    tracing::info!("{} entries received, {} not, {:?} spent syncing", summary.ok, summary.issues.len(), summary.sync_time);
+}
+
+/// fails when the upload can only go over the quota of `account`, even overwriting everything in the share.
+/// The destination isn't scanned, files going over the quota are refused one by one while receiving.
+fn check_quota(account: &commons::quota::Account, metadata: &commons::UploadMetadata) -> Result<(), commons::error::Error> {
+    account.admits(metadata.bytes, metadata.count as u64)
+}
+
+/// fails when the upload asks for mirroring without the server allowing it, clients aren't authenticated
+fn check_mirror(metadata: &commons::UploadMetadata, allow_mirror: bool) -> Result<(), commons::error::Error> {
+    match metadata.mirror.is_some() && !allow_mirror {
+        true => Err(commons::error::Error::invalid_request("mirroring uploads is not allowed by this server, see --allow-mirror")),
+        false => Ok(()),
+    }
+}
+
+/// fails when the upload doesn't fit on the destination filesystem without eating into `reserve`
+fn check_space(metadata: &commons::UploadMetadata, reserve: u64) -> Result<(), commons::error::Error> {
+    let available = commons::available_space(&metadata.destination)?;
+    match metadata.bytes.saturating_add(reserve) > available {
+        true => Err(commons::error::Error::InvalidRequest(format!("not enough space, {} bytes to upload with {} available and {} reserved", metadata.bytes, available, reserve))),
+        false => Ok(()),
+    }
+}